@@ -0,0 +1,5 @@
+CREATE TABLE todos (
+    id INTEGER NOT NULL PRIMARY KEY,
+    name STRING NOT NULL,
+    done BOOL NOT NULL DEFAULT false
+);
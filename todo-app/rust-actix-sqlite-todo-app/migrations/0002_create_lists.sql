@@ -0,0 +1,23 @@
+CREATE TABLE lists (
+    id INTEGER NOT NULL PRIMARY KEY,
+    name STRING NOT NULL
+);
+
+-- Existing todos are moved into a default list.
+INSERT INTO lists (id, name) VALUES (1, 'Todos');
+
+-- SQLite can't add a NOT NULL foreign key column in place, so rebuild the table.
+CREATE TABLE todos_new (
+    id INTEGER NOT NULL PRIMARY KEY,
+    name STRING NOT NULL,
+    done BOOL NOT NULL DEFAULT false,
+    list_id INTEGER NOT NULL REFERENCES lists (id) ON DELETE CASCADE
+);
+
+INSERT INTO todos_new (id, name, done, list_id) SELECT id, name, done, 1 FROM todos;
+
+DROP TABLE todos;
+
+ALTER TABLE todos_new RENAME TO todos;
+
+CREATE INDEX todos_list_id ON todos (list_id);
@@ -0,0 +1,10 @@
+CREATE TABLE users (
+    id INTEGER NOT NULL PRIMARY KEY,
+    username STRING NOT NULL UNIQUE,
+    password_hash STRING NOT NULL
+);
+
+-- Lists created before accounts existed have no owner; the first user to register adopts them.
+ALTER TABLE lists ADD COLUMN user_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+
+CREATE INDEX lists_user_id ON lists (user_id);
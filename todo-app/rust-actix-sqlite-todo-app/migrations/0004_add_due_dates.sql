@@ -0,0 +1,6 @@
+ALTER TABLE todos ADD COLUMN due_date DATE;
+
+-- Only meaningful alongside `due_date`.
+ALTER TABLE todos ADD COLUMN due_time TIME;
+
+CREATE INDEX todos_due_date ON todos (due_date, due_time);
@@ -0,0 +1,17 @@
+-- Full-text index over todo names, kept in sync with `todos` by the triggers below.
+CREATE VIRTUAL TABLE todos_fts USING fts5 (name, content = 'todos', content_rowid = 'id');
+
+INSERT INTO todos_fts (todos_fts) VALUES ('rebuild');
+
+CREATE TRIGGER todos_fts_insert AFTER INSERT ON todos BEGIN
+    INSERT INTO todos_fts (rowid, name) VALUES (new.id, new.name);
+END;
+
+CREATE TRIGGER todos_fts_delete AFTER DELETE ON todos BEGIN
+    INSERT INTO todos_fts (todos_fts, rowid, name) VALUES ('delete', old.id, old.name);
+END;
+
+CREATE TRIGGER todos_fts_update AFTER UPDATE OF name ON todos BEGIN
+    INSERT INTO todos_fts (todos_fts, rowid, name) VALUES ('delete', old.id, old.name);
+    INSERT INTO todos_fts (rowid, name) VALUES (new.id, new.name);
+END;
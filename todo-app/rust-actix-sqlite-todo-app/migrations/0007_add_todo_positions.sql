@@ -0,0 +1,9 @@
+-- Manual order of todos within their list, starting from 0.
+ALTER TABLE todos ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+UPDATE todos SET position = (
+    SELECT COUNT(*) FROM todos AS earlier
+    WHERE earlier.list_id = todos.list_id AND earlier.id < todos.id
+);
+
+CREATE INDEX todos_list_id_position ON todos (list_id, position);
@@ -0,0 +1,5 @@
+-- 0 = none, 1 = low, 2 = medium, 3 = high, 4 = urgent.
+ALTER TABLE todos ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+
+-- How the list page orders a list: 'manual', 'priority', 'due' or 'created'.
+ALTER TABLE lists ADD COLUMN sort TEXT NOT NULL DEFAULT 'manual';
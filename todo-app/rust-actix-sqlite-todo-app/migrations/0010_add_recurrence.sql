@@ -0,0 +1,3 @@
+-- An RFC 5545 RRULE value like 'FREQ=WEEKLY;BYDAY=MO,WE', or NULL for
+-- todos that don't repeat.
+ALTER TABLE todos ADD COLUMN rrule TEXT;
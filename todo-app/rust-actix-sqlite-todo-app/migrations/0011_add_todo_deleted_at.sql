@@ -0,0 +1,4 @@
+-- When the todo was moved to the trash (UTC), or NULL if it wasn't.
+ALTER TABLE todos ADD COLUMN deleted_at DATETIME;
+
+CREATE INDEX todos_deleted_at ON todos (deleted_at);
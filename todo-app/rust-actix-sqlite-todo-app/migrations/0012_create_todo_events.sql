@@ -0,0 +1,26 @@
+-- Append-only log of changes to todos. `todo_id` deliberately isn't a
+-- foreign key, so a todo's history outlives the todo itself.
+CREATE TABLE todo_events (
+    id INTEGER PRIMARY KEY NOT NULL,
+    todo_id INTEGER NOT NULL,
+    -- NULL for changes the server makes by itself, like purging old trash.
+    actor_id INTEGER REFERENCES users (id),
+    -- 'created', 'updated', 'deleted', 'restored' or 'purged'.
+    action TEXT NOT NULL,
+    -- For 'updated', the field that changed and its old and new values. For
+    -- everything else, `after` is the todo's name at the time.
+    field TEXT,
+    before TEXT,
+    after TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+
+CREATE INDEX todo_events_todo_id ON todo_events (todo_id, id);
+
+CREATE TRIGGER todo_events_no_update BEFORE UPDATE ON todo_events BEGIN
+    SELECT RAISE(ABORT, 'todo_events is append-only');
+END;
+
+CREATE TRIGGER todo_events_no_delete BEFORE DELETE ON todo_events BEGIN
+    SELECT RAISE(ABORT, 'todo_events is append-only');
+END;
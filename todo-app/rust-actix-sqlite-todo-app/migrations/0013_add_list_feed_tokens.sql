@@ -0,0 +1,5 @@
+-- The secret in a list's calendar feed URL, created the first time it's
+-- asked for. Calendar clients can't log in, so the URL is all they need.
+ALTER TABLE lists ADD COLUMN feed_token TEXT;
+
+CREATE UNIQUE INDEX lists_feed_token ON lists (feed_token);
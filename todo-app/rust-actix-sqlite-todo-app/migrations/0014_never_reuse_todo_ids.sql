@@ -0,0 +1,72 @@
+-- `todo_events` outlives the todos it's about, so a todo id must never be
+-- handed out twice: without AUTOINCREMENT, SQLite reuses the highest id once
+-- that todo is purged, and the new todo would inherit the old one's history.
+-- SQLite can't add AUTOINCREMENT in place, so rebuild the table.
+--
+-- Migrations run in a transaction, where foreign keys can't be switched off.
+-- Checks wait until the end instead, so subtasks can be copied before their
+-- parents, and the tags are set aside because dropping the old table deletes
+-- them.
+PRAGMA defer_foreign_keys = ON;
+
+CREATE TEMPORARY TABLE todo_tags_saved AS SELECT * FROM todo_tags;
+
+CREATE TABLE todos_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name STRING NOT NULL,
+    done BOOL NOT NULL DEFAULT false,
+    list_id INTEGER NOT NULL REFERENCES lists (id) ON DELETE CASCADE,
+    due_date DATE,
+    due_time TIME,
+    position INTEGER NOT NULL DEFAULT 0,
+    parent_id INTEGER REFERENCES todos_new (id) ON DELETE CASCADE,
+    priority INTEGER NOT NULL DEFAULT 0,
+    rrule TEXT,
+    deleted_at DATETIME
+);
+
+INSERT INTO todos_new (id, name, done, list_id, due_date, due_time, position, parent_id, priority, rrule, deleted_at)
+SELECT id, name, done, list_id, due_date, due_time, position, parent_id, priority, rrule, deleted_at FROM todos;
+
+-- The full-text index's triggers would fire on the rows the drop deletes,
+-- and fail; they're recreated below.
+DROP TRIGGER todos_fts_insert;
+DROP TRIGGER todos_fts_delete;
+DROP TRIGGER todos_fts_update;
+
+DROP TABLE todos;
+
+ALTER TABLE todos_new RENAME TO todos;
+
+INSERT INTO todo_tags SELECT * FROM todo_tags_saved;
+
+DROP TABLE todo_tags_saved;
+
+-- Todos purged before this migration left history behind too, so start
+-- after the highest id either table has seen.
+DELETE FROM sqlite_sequence WHERE name = 'todos';
+
+INSERT INTO sqlite_sequence (name, seq) VALUES ('todos', MAX(
+    (SELECT COALESCE(MAX(id), 0) FROM todos),
+    (SELECT COALESCE(MAX(todo_id), 0) FROM todo_events)
+));
+
+CREATE INDEX todos_list_id ON todos (list_id);
+CREATE INDEX todos_due_date ON todos (due_date, due_time);
+CREATE INDEX todos_list_id_position ON todos (list_id, position);
+CREATE INDEX todos_parent_id ON todos (parent_id);
+CREATE INDEX todos_deleted_at ON todos (deleted_at);
+
+-- The full-text index keeps its rows, since the ids didn't change.
+CREATE TRIGGER todos_fts_insert AFTER INSERT ON todos BEGIN
+    INSERT INTO todos_fts (rowid, name) VALUES (new.id, new.name);
+END;
+
+CREATE TRIGGER todos_fts_delete AFTER DELETE ON todos BEGIN
+    INSERT INTO todos_fts (todos_fts, rowid, name) VALUES ('delete', old.id, old.name);
+END;
+
+CREATE TRIGGER todos_fts_update AFTER UPDATE OF name ON todos BEGIN
+    INSERT INTO todos_fts (todos_fts, rowid, name) VALUES ('delete', old.id, old.name);
+    INSERT INTO todos_fts (rowid, name) VALUES (new.id, new.name);
+END;
@@ -0,0 +1,465 @@
+use actix_session::SessionExt;
+use actix_web::{
+    delete, dev::Payload, get, http::StatusCode, patch, post, web, FromRequest, HttpRequest,
+    HttpResponse, ResponseError,
+};
+use chrono::{Local, NaiveDate, NaiveTime};
+use derive_more::Display;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use crate::{
+    history, recurrence, reorder, subtasks, tags, trash, Change, CurrentUser, Error, Priority,
+    State, Todo,
+};
+
+/// An `Error` as API clients see it: an RFC 7807 problem document rather
+/// than an HTML page, and a 401 rather than a redirect to the login form.
+#[derive(Debug, Display)]
+struct ApiError(Error);
+
+/// An RFC 7807 `application/problem+json` body.
+#[derive(Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.0.status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .json(Problem {
+                // The status code says it all, so there's no problem type URI.
+                kind: "about:blank",
+                title: status.canonical_reason().unwrap_or("Error"),
+                status: status.as_u16(),
+                detail: self.0.message(),
+            })
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(value: sqlx::Error) -> Self {
+        ApiError(value.into())
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(value: Error) -> Self {
+        ApiError(value)
+    }
+}
+
+/// The logged-in user, for API routes. Unlike `CurrentUser` this answers
+/// with a 401 instead of redirecting to the login page.
+struct ApiUser(CurrentUser);
+
+impl FromRequest for ApiUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            CurrentUser::from_session(&req.get_session())
+                .map(ApiUser)
+                .ok_or(ApiError(Error::Unauthorized)),
+        )
+    }
+}
+
+async fn check_todo(state: &State, user: &CurrentUser, id: u32) -> Result<(), ApiError> {
+    if !user.owns_todo(&state.db, id).await? {
+        return Err(Error::NotFound.into());
+    }
+
+    Ok(())
+}
+
+async fn check_list(state: &State, user: &CurrentUser, list_id: u32) -> Result<(), ApiError> {
+    if !user.owns_list(&state.db, list_id).await? {
+        return Err(Error::Validation("list does not exist".to_string()).into());
+    }
+
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), ApiError> {
+    if name.trim().is_empty() {
+        return Err(Error::Validation("name must not be empty".to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// Checks and normalizes an RRULE value.
+fn validate_rrule(rrule: Option<&str>) -> Result<Option<String>, ApiError> {
+    match rrule {
+        Some(rrule) => recurrence::parse_rrule(rrule)
+            .map_err(|message| Error::Validation(format!("invalid rrule: {}", message)).into()),
+        None => Ok(None),
+    }
+}
+
+/// Applies the same splitting and de-duplication as the HTML tag field.
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    tags::parse_tags(&tags.join(" "))
+}
+
+async fn fetch_todo(state: &State, id: u32) -> Result<Todo, ApiError> {
+    let todo = sqlx::query_as::<_, Todo>(
+        "
+            SELECT * FROM todos WHERE id=?
+        ",
+    )
+    .bind(id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let mut todos = [todo];
+    tags::load_tags(&state.db, &mut todos).await?;
+    let [todo] = todos;
+
+    Ok(todo)
+}
+
+#[derive(Deserialize)]
+struct ListTodosQuery {
+    list_id: Option<u32>,
+    tag: Option<String>,
+}
+
+#[get("/todos")]
+async fn list_todos(
+    state: web::Data<Arc<State>>,
+    ApiUser(user): ApiUser,
+    web::Query(query): web::Query<ListTodosQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let mut todos = sqlx::query_as::<_, Todo>(
+        "
+            SELECT todos.* FROM todos JOIN lists ON lists.id = todos.list_id
+            WHERE lists.user_id = ?1 AND (?2 IS NULL OR todos.list_id = ?2)
+            AND todos.deleted_at IS NULL
+            AND (?3 IS NULL OR todos.id IN (
+                SELECT todo_id FROM todo_tags JOIN tags ON tags.id = todo_tags.tag_id
+                WHERE tags.name = ?3
+            ))
+            ORDER BY todos.list_id, todos.position, todos.id
+        ",
+    )
+    .bind(user.id)
+    .bind(query.list_id)
+    .bind(query.tag)
+    .fetch_all(&state.db)
+    .await?;
+
+    tags::load_tags(&state.db, &mut todos).await?;
+
+    Ok(HttpResponse::Ok().json(todos))
+}
+
+#[get("/todos/{id}")]
+async fn get_todo(
+    state: web::Data<Arc<State>>,
+    ApiUser(user): ApiUser,
+    id: web::Path<u32>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+
+    check_todo(&state, &user, id).await?;
+
+    let todo = fetch_todo(&state, id).await?;
+
+    Ok(HttpResponse::Ok().json(todo))
+}
+
+#[derive(Deserialize)]
+struct CreateTodo {
+    name: String,
+    #[serde(default)]
+    done: bool,
+    /// Defaults to the parent's list, or else the user's first list.
+    list_id: Option<u32>,
+    due_date: Option<NaiveDate>,
+    due_time: Option<NaiveTime>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Makes the new todo a subtask of this one.
+    parent_id: Option<u32>,
+    #[serde(default)]
+    priority: Priority,
+    /// An RRULE value like `"FREQ=WEEKLY;BYDAY=MO"`.
+    rrule: Option<String>,
+}
+
+#[post("/todos")]
+async fn create_todo(
+    state: web::Data<Arc<State>>,
+    ApiUser(user): ApiUser,
+    web::Json(body): web::Json<CreateTodo>,
+) -> Result<HttpResponse, ApiError> {
+    validate_name(&body.name)?;
+    let rrule = validate_rrule(body.rrule.as_deref())?;
+
+    let parent_list_id = match body.parent_id {
+        Some(parent_id) => {
+            check_todo(&state, &user, parent_id).await?;
+            Some(fetch_todo(&state, parent_id).await?.list_id)
+        }
+        None => None,
+    };
+
+    let list_id = match (parent_list_id, body.list_id) {
+        (Some(parent_list_id), Some(list_id)) if parent_list_id != list_id => {
+            return Err(
+                Error::Validation("subtasks must be in their parent's list".to_string()).into(),
+            )
+        }
+        (Some(list_id), _) | (None, Some(list_id)) => list_id,
+        (None, None) => sqlx::query_scalar::<_, u32>(
+            "
+                SELECT id FROM lists WHERE user_id=? ORDER BY id LIMIT 1
+            ",
+        )
+        .bind(user.id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| Error::Validation("list does not exist".to_string()))?,
+    };
+
+    check_list(&state, &user, list_id).await?;
+
+    let mut tx = state.db.begin().await?;
+
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+            INSERT INTO todos (name, done, list_id, due_date, due_time, parent_id, priority, rrule, position)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, (
+                SELECT COALESCE(MAX(position) + 1, 0) FROM todos
+                WHERE list_id = ?3 AND parent_id IS ?6
+            ))
+            RETURNING id
+        ",
+    )
+    .bind(body.name.trim())
+    .bind(body.done)
+    .bind(list_id)
+    .bind(body.due_date)
+    .bind(body.due_time.filter(|_| body.due_date.is_some()))
+    .bind(body.parent_id)
+    .bind(body.priority)
+    .bind(rrule)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tags::set_tags(&mut tx, user.id, id, &normalize_tags(&body.tags)).await?;
+    history::record(&mut tx, id, Some(user.id), history::Action::Created).await?;
+
+    tx.commit().await?;
+
+    state.live.publish(user.id, Change::Created, id, list_id);
+
+    let todo = fetch_todo(&state, id).await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", format!("/api/v1/todos/{}", todo.id)))
+        .json(todo))
+}
+
+/// Distinguishes a field that's missing (`None`) from one that's explicitly
+/// `null` (`Some(None)`), so PATCH can clear nullable columns.
+fn double_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+struct UpdateTodo {
+    name: Option<String>,
+    done: Option<bool>,
+    /// Moves the todo and its subtasks to another list, detaching it from
+    /// its parent.
+    list_id: Option<u32>,
+    #[serde(default, deserialize_with = "double_option")]
+    due_date: Option<Option<NaiveDate>>,
+    #[serde(default, deserialize_with = "double_option")]
+    due_time: Option<Option<NaiveTime>>,
+    priority: Option<Priority>,
+    #[serde(default, deserialize_with = "double_option")]
+    rrule: Option<Option<String>>,
+    /// Replaces all of the todo's tags when present.
+    tags: Option<Vec<String>>,
+    /// Moves the todo to this index among its siblings.
+    position: Option<i64>,
+}
+
+#[patch("/todos/{id}")]
+async fn update_todo(
+    state: web::Data<Arc<State>>,
+    ApiUser(user): ApiUser,
+    id: web::Path<u32>,
+    web::Json(body): web::Json<UpdateTodo>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+
+    if let Some(name) = &body.name {
+        validate_name(name)?;
+    }
+    let rrule = validate_rrule(body.rrule.as_ref().and_then(Option::as_deref))?;
+
+    check_todo(&state, &user, id).await?;
+
+    if let Some(list_id) = body.list_id {
+        check_list(&state, &user, list_id).await?;
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let before = history::snapshot(&mut tx, id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    sqlx::query(
+        "
+            UPDATE todos
+            SET name = COALESCE(?, name), done = COALESCE(?, done), priority = COALESCE(?, priority),
+                rrule = CASE WHEN ? THEN ? ELSE rrule END,
+                due_date = CASE WHEN ? THEN ? ELSE due_date END,
+                due_time = CASE WHEN ? THEN ? ELSE due_time END
+            WHERE id=?
+        ",
+    )
+    .bind(body.name.as_deref().map(str::trim))
+    .bind(body.done)
+    .bind(body.priority)
+    .bind(body.rrule.is_some())
+    .bind(rrule)
+    .bind(body.due_date.is_some())
+    .bind(body.due_date.flatten())
+    .bind(body.due_time.is_some())
+    .bind(body.due_time.flatten())
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(list_id) = body.list_id {
+        subtasks::move_to_list(&mut tx, id, list_id).await?;
+    }
+
+    if let Some(tags) = &body.tags {
+        tags::set_tags(&mut tx, user.id, id, &normalize_tags(tags)).await?;
+    }
+
+    if let Some(position) = body.position {
+        reorder::set_position(&mut tx, id, position).await?;
+    }
+
+    let after = history::snapshot(&mut tx, id)
+        .await?
+        .ok_or(Error::NotFound)?;
+    history::record_changes(&mut tx, user.id, &before, &after).await?;
+
+    let next = if after.done && !before.done {
+        let today = Local::now().date_naive();
+        recurrence::create_next_occurrence(&mut tx, id, user.id, today).await?
+    } else {
+        None
+    };
+
+    tx.commit().await?;
+
+    // A todo moved to another list is gone from the old one and new in the other.
+    if after.list_id != before.list_id {
+        state
+            .live
+            .publish(user.id, Change::Deleted, id, before.list_id);
+        state
+            .live
+            .publish(user.id, Change::Created, id, after.list_id);
+    } else {
+        state
+            .live
+            .publish(user.id, Change::Updated, id, after.list_id);
+    }
+    if let Some(next) = next {
+        state
+            .live
+            .publish(user.id, Change::Created, next, after.list_id);
+    }
+
+    let todo = fetch_todo(&state, id).await?;
+
+    Ok(HttpResponse::Ok().json(todo))
+}
+
+#[delete("/todos/{id}")]
+async fn delete_todo(
+    state: web::Data<Arc<State>>,
+    ApiUser(user): ApiUser,
+    id: web::Path<u32>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+
+    check_todo(&state, &user, id).await?;
+
+    let mut tx = state.db.begin().await?;
+
+    if !trash::trash_todo(&mut tx, id, user.id).await? {
+        return Err(Error::NotFound.into());
+    }
+
+    let list_id = sqlx::query_scalar::<_, u32>(
+        "
+            SELECT list_id FROM todos WHERE id=?
+        ",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    state.live.publish(user.id, Change::Deleted, id, list_id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Mounts the JSON API. Malformed request bodies and query strings are
+/// answered with a 422 problem like the handlers' own.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/v1")
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|err, _req| ApiError(Error::Validation(err.to_string())).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _req| ApiError(Error::Validation(err.to_string())).into()),
+            )
+            .app_data(
+                web::PathConfig::default()
+                    .error_handler(|_err, _req| ApiError(Error::NotFound).into()),
+            )
+            .default_service(web::to(|| async {
+                Err::<HttpResponse, _>(ApiError(Error::NotFound))
+            }))
+            .service(list_todos)
+            .service(get_todo)
+            .service(create_todo)
+            .service(update_todo)
+            .service(delete_todo),
+    );
+}
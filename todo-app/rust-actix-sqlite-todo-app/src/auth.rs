@@ -0,0 +1,348 @@
+use actix_session::{Session, SessionExt};
+use actix_web::{
+    dev::Payload,
+    get,
+    http::{header::ContentType, StatusCode},
+    post, web, FromRequest, HttpRequest, HttpResponse, Responder,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use maud::{html, Markup};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use crate::csrf::CsrfToken;
+use crate::{page, Error, State};
+
+const USER_ID_KEY: &str = "user_id";
+
+/// Checked against when a login names no account, so that takes as long as
+/// a wrong password and doesn't give away which usernames exist. Hashed with
+/// the same settings as real passwords.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$pEVu6d5JgxpdVn6inj9tdQ$cDxx+C5idibBqXwyDZC9dmt3VhuTHBQ5sfM/xDj2Dcs";
+
+/// The logged-in user. Extracting it from a request without a session
+/// redirects the browser to the login page.
+pub(crate) struct CurrentUser {
+    pub(crate) id: u32,
+}
+
+impl CurrentUser {
+    pub(crate) fn from_session(session: &Session) -> Option<Self> {
+        session
+            .get::<u32>(USER_ID_KEY)
+            .ok()
+            .flatten()
+            .map(|id| CurrentUser { id })
+    }
+
+    /// Whether list `list_id` exists and belongs to this user. Fails with
+    /// `Error::Forbidden` if it belongs to someone else.
+    pub(crate) async fn owns_list(&self, db: &SqlitePool, list_id: u32) -> Result<bool, Error> {
+        let owner = sqlx::query_scalar::<_, Option<u32>>(
+            "
+                SELECT user_id FROM lists WHERE id=?
+            ",
+        )
+        .bind(list_id)
+        .fetch_optional(db)
+        .await?;
+
+        self.check_owner(owner)
+    }
+
+    /// Whether todo `todo_id` exists outside the trash and belongs to this
+    /// user. Fails with `Error::Forbidden` if it belongs to someone else.
+    pub(crate) async fn owns_todo(&self, db: &SqlitePool, todo_id: u32) -> Result<bool, Error> {
+        self.owns_todo_in(db, todo_id, false).await
+    }
+
+    /// Like `owns_todo`, but for todos in the trash.
+    pub(crate) async fn owns_deleted_todo(
+        &self,
+        db: &SqlitePool,
+        todo_id: u32,
+    ) -> Result<bool, Error> {
+        self.owns_todo_in(db, todo_id, true).await
+    }
+
+    async fn owns_todo_in(
+        &self,
+        db: &SqlitePool,
+        todo_id: u32,
+        trash: bool,
+    ) -> Result<bool, Error> {
+        let owner = sqlx::query_scalar::<_, Option<u32>>(
+            "
+                SELECT lists.user_id FROM todos JOIN lists ON lists.id = todos.list_id
+                WHERE todos.id=? AND (todos.deleted_at IS NOT NULL) = ?
+            ",
+        )
+        .bind(todo_id)
+        .bind(trash)
+        .fetch_optional(db)
+        .await?;
+
+        self.check_owner(owner)
+    }
+
+    fn check_owner(&self, owner: Option<Option<u32>>) -> Result<bool, Error> {
+        match owner {
+            None => Ok(false),
+            Some(Some(owner)) if owner == self.id => Ok(true),
+            Some(_) => Err(Error::Forbidden),
+        }
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(CurrentUser::from_session(&req.get_session()).ok_or(Error::Unauthorized))
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct User {
+    id: u32,
+    password_hash: String,
+}
+
+#[derive(Deserialize)]
+struct CredentialsForm {
+    username: String,
+    password: String,
+}
+
+fn credentials_page(title: &str, action: &str, error: Option<&str>, csrf: &CsrfToken) -> Markup {
+    page(
+        title,
+        html! {
+            h1 { (title) }
+            @if let Some(error) = error {
+                p class="error" { (error) }
+            }
+            form class="credentials" action=(action) method="POST" {
+                (csrf)
+                input name="username" placeholder="Username" autocomplete="username" required autofocus;
+                input name="password" type="password" placeholder="Password" required;
+                button { (title) }
+            }
+            @if action == "/login" {
+                p { "No account yet? " a href="/register" { "Register" } }
+            } @else {
+                p { "Already have an account? " a href="/login" { "Log in" } }
+            }
+        },
+    )
+}
+
+fn credentials_error(
+    status: StatusCode,
+    title: &str,
+    action: &str,
+    error: &str,
+    csrf: &CsrfToken,
+) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(credentials_page(title, action, Some(error), csrf).into_string())
+}
+
+async fn hash_password(password: String) -> Result<String, Error> {
+    web::block(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(|_| Error::PasswordHash)?
+    .map_err(|_| Error::PasswordHash)
+}
+
+async fn verify_password(password: String, password_hash: String) -> Result<bool, Error> {
+    web::block(move || {
+        let hash = PasswordHash::new(&password_hash).map_err(|_| Error::PasswordHash)?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    })
+    .await
+    .map_err(|_| Error::PasswordHash)?
+}
+
+fn log_in(session: &Session, user_id: u32) -> Result<(), Error> {
+    session.renew();
+    session
+        .insert(USER_ID_KEY, user_id)
+        .map_err(|_| Error::Session)
+}
+
+#[get("/login")]
+async fn login_page(csrf: CsrfToken) -> Markup {
+    credentials_page("Log in", "/login", None, &csrf)
+}
+
+#[post("/login")]
+async fn login(
+    state: web::Data<Arc<State>>,
+    session: Session,
+    csrf: CsrfToken,
+    web::Form(form): web::Form<CredentialsForm>,
+) -> Result<HttpResponse, Error> {
+    let user = sqlx::query_as::<_, User>(
+        "
+            SELECT id, password_hash FROM users WHERE username=?
+        ",
+    )
+    .bind(form.username.trim())
+    .fetch_optional(&state.db)
+    .await?;
+
+    let password_hash = user
+        .as_ref()
+        .map_or(DUMMY_PASSWORD_HASH, |user| &user.password_hash)
+        .to_string();
+    let verified = verify_password(form.password, password_hash).await?;
+
+    let user = match user {
+        Some(user) if verified => user,
+        _ => {
+            return Ok(credentials_error(
+                StatusCode::UNAUTHORIZED,
+                "Log in",
+                "/login",
+                "Wrong username or password",
+                &csrf,
+            ))
+        }
+    };
+
+    log_in(&session, user.id)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/"))
+        .finish())
+}
+
+#[get("/register")]
+async fn register_page(csrf: CsrfToken) -> Markup {
+    credentials_page("Register", "/register", None, &csrf)
+}
+
+/// Creates an account and logs it in. The first account adopts any lists
+/// left over from before accounts existed; otherwise it starts with an
+/// empty "Todos" list.
+#[post("/register")]
+async fn register(
+    state: web::Data<Arc<State>>,
+    session: Session,
+    csrf: CsrfToken,
+    web::Form(form): web::Form<CredentialsForm>,
+) -> Result<HttpResponse, Error> {
+    let username = form.username.trim();
+
+    if username.is_empty() || form.password.is_empty() {
+        return Ok(credentials_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Register",
+            "/register",
+            "Username and password are required",
+            &csrf,
+        ));
+    }
+
+    let password_hash = hash_password(form.password).await?;
+
+    let mut tx = state.db.begin().await?;
+
+    let user_id = sqlx::query_scalar::<_, u32>(
+        "
+            INSERT INTO users (username, password_hash) VALUES (?, ?)
+            ON CONFLICT (username) DO NOTHING
+            RETURNING id
+        ",
+    )
+    .bind(username)
+    .bind(password_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(user_id) = user_id else {
+        return Ok(credentials_error(
+            StatusCode::CONFLICT,
+            "Register",
+            "/register",
+            "That username is taken",
+            &csrf,
+        ));
+    };
+
+    let adopted = sqlx::query(
+        "
+            UPDATE lists SET user_id=? WHERE user_id IS NULL
+        ",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    if adopted.rows_affected() == 0 {
+        sqlx::query(
+            "
+                INSERT INTO lists (name, user_id) VALUES ('Todos', ?)
+            ",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    log_in(&session, user_id)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/"))
+        .finish())
+}
+
+#[post("/logout")]
+async fn logout(session: Session) -> impl Responder {
+    session.purge();
+
+    web::Redirect::to("/login").see_other()
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(login_page)
+        .service(login)
+        .service(register_page)
+        .service(register)
+        .service(logout);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::Params;
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_a_real_one() {
+        let hash = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        assert_eq!(hash.algorithm, argon2::Algorithm::default().ident());
+        let (params, default) = (Params::try_from(&hash).unwrap(), Params::default());
+        assert_eq!(
+            (params.m_cost(), params.t_cost(), params.p_cost()),
+            (default.m_cost(), default.t_cost(), default.p_cost())
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use actix_session::Session;
+use actix_web::{post, web, HttpRequest, Responder};
+use chrono::Local;
+use maud::{html, Markup};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+
+use crate::csrf::CsrfToken;
+use crate::flash::Flash;
+use crate::{history, items, recurrence, subtasks, trash, Change, CurrentUser, Error, List, State};
+
+/// What to do with the selected todos.
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Complete,
+    Delete,
+    Move,
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "complete" => Some(Action::Complete),
+            "delete" => Some(Action::Delete),
+            "move" => Some(Action::Move),
+            _ => None,
+        }
+    }
+}
+
+/// The bulk actions form. The checkboxes add an `ids` field per selected
+/// todo, which `web::Form` can't collect into a struct, so the fields are
+/// read one by one.
+struct BulkForm {
+    action: Action,
+    ids: Vec<u32>,
+    /// The list the form was on.
+    list_id: u32,
+    /// Where `Action::Move` moves the todos.
+    to_list_id: Option<u32>,
+}
+
+impl BulkForm {
+    fn parse(fields: Vec<(String, String)>) -> Result<Self, Error> {
+        let invalid = |field: &str| Error::Validation(format!("Invalid {}", field));
+
+        let (mut action, mut ids, mut list_id, mut to_list_id) = (None, Vec::new(), None, None);
+        for (name, value) in fields {
+            match name.as_str() {
+                "action" => {
+                    action = Some(Action::from_name(&value).ok_or_else(|| invalid("action"))?)
+                }
+                "ids" => ids.push(value.parse().map_err(|_| invalid("todo id"))?),
+                "list_id" => list_id = Some(value.parse().map_err(|_| invalid("list id"))?),
+                "to_list_id" if value.is_empty() => {}
+                "to_list_id" => to_list_id = Some(value.parse().map_err(|_| invalid("list id"))?),
+                _ => {}
+            }
+        }
+
+        if ids.is_empty() {
+            return Err(Error::Validation("Select some todos first".to_string()));
+        }
+        let action = action.ok_or_else(|| invalid("action"))?;
+        if action == Action::Move && to_list_id.is_none() {
+            return Err(Error::Validation(
+                "Pick a list to move the todos to".to_string(),
+            ));
+        }
+
+        Ok(BulkForm {
+            action,
+            ids,
+            list_id: list_id.ok_or_else(|| invalid("list id"))?,
+            to_list_id,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ListForm {
+    list_id: u32,
+}
+
+/// How many todos are above `todo_id`.
+async fn depth(conn: &mut SqliteConnection, todo_id: u32) -> Result<u32, sqlx::Error> {
+    sqlx::query_scalar::<_, u32>(
+        "
+            WITH RECURSIVE ancestors(id) AS (
+                SELECT parent_id FROM todos WHERE id = ?
+                UNION ALL
+                SELECT todos.parent_id FROM todos JOIN ancestors ON todos.id = ancestors.id
+            )
+            SELECT COUNT(id) FROM ancestors
+        ",
+    )
+    .bind(todo_id)
+    .fetch_one(&mut *conn)
+    .await
+}
+
+/// Marks todo `todo_id` done if it isn't, recording it and creating the next
+/// occurrence of recurring todos. Returns the todo's list and the next
+/// occurrence, or `None` if there was nothing to do.
+pub(crate) async fn complete(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+    actor_id: u32,
+) -> Result<Option<(u32, Option<u32>)>, sqlx::Error> {
+    let Some(list_id) = sqlx::query_scalar::<_, u32>(
+        "
+            UPDATE todos SET done = true WHERE id=? AND deleted_at IS NULL AND NOT done
+            RETURNING list_id
+        ",
+    )
+    .bind(todo_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    history::record_change(
+        conn,
+        todo_id,
+        actor_id,
+        "done",
+        Some(false.to_string()),
+        Some(true.to_string()),
+    )
+    .await?;
+
+    let today = Local::now().date_naive();
+    let next = recurrence::create_next_occurrence(conn, todo_id, actor_id, today).await?;
+
+    Ok(Some((list_id, next)))
+}
+
+/// The banner for `count` todos moved to the trash, if there were any.
+fn trashed_flash(count: usize) -> Option<Flash> {
+    (count > 0).then_some(Flash::Trashed { count })
+}
+
+/// Completes, deletes or moves the todos ticked on a list page, all or
+/// nothing.
+#[post("/todos/bulk")]
+async fn bulk(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
+    web::Form(fields): web::Form<Vec<(String, String)>>,
+) -> Result<impl Responder, Error> {
+    let form = BulkForm::parse(fields)?;
+
+    if !user.owns_list(&state.db, form.list_id).await? {
+        return Err(Error::NotFound);
+    }
+    if let Some(to_list_id) = form.to_list_id {
+        if !user.owns_list(&state.db, to_list_id).await? {
+            return Err(Error::NotFound);
+        }
+    }
+    for &id in &form.ids {
+        if !user.owns_todo(&state.db, id).await? {
+            return Err(Error::NotFound);
+        }
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    // Parents first, so a selected subtask goes along with its selected
+    // parent instead of being moved on its own.
+    let mut ids = Vec::new();
+    for &id in &form.ids {
+        ids.push((depth(&mut tx, id).await?, id));
+    }
+    ids.sort_unstable();
+    ids.dedup();
+
+    // (change, todo, list) for every todo that changed, to publish once the
+    // transaction is committed.
+    let mut changes = Vec::new();
+    let mut flash = None;
+
+    match form.action {
+        Action::Complete => {
+            for (_, id) in ids {
+                if let Some((list_id, next)) = complete(&mut tx, id, user.id).await? {
+                    changes.push((Change::Updated, id, list_id));
+                    changes.extend(next.map(|next| (Change::Created, next, list_id)));
+                }
+            }
+        }
+        Action::Delete => {
+            let mut trashed = 0;
+            for (_, id) in ids {
+                let list_id = sqlx::query_scalar::<_, u32>(
+                    "
+                        SELECT list_id FROM todos WHERE id=?
+                    ",
+                )
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+                // A subtask of a todo deleted before it is already gone.
+                if trash::trash_todo(&mut tx, id, user.id).await? {
+                    trashed += 1;
+                    changes.push((Change::Deleted, id, list_id));
+                }
+            }
+            flash = trashed_flash(trashed);
+        }
+        Action::Move => {
+            let to_list_id = form.to_list_id.unwrap_or(form.list_id);
+            for (_, id) in ids {
+                let before = history::snapshot(&mut tx, id)
+                    .await?
+                    .ok_or(Error::NotFound)?;
+                subtasks::move_to_list(&mut tx, id, to_list_id).await?;
+                let after = history::snapshot(&mut tx, id)
+                    .await?
+                    .ok_or(Error::NotFound)?;
+                history::record_changes(&mut tx, user.id, &before, &after).await?;
+
+                // A todo moved to another list is gone from the old one and
+                // new in the other.
+                if after.list_id != before.list_id {
+                    changes.push((Change::Deleted, id, before.list_id));
+                    changes.push((Change::Created, id, after.list_id));
+                }
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    for (change, id, list_id) in changes {
+        state.live.publish(user.id, change, id, list_id);
+    }
+
+    items::reply(&state, &request, &session, form.list_id, &[], flash).await
+}
+
+/// Moves every completed todo in a list to the trash.
+#[post("/todos/clear-completed")]
+async fn clear_completed(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
+    web::Form(form): web::Form<ListForm>,
+) -> Result<impl Responder, Error> {
+    if !user.owns_list(&state.db, form.list_id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let completed = sqlx::query_scalar::<_, u32>(
+        "
+            SELECT id FROM todos WHERE list_id=? AND done AND deleted_at IS NULL
+        ",
+    )
+    .bind(form.list_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut trashed = Vec::new();
+    for id in completed {
+        // A completed subtask of a completed todo goes along with it.
+        if trash::trash_todo(&mut tx, id, user.id).await? {
+            trashed.push(id);
+        }
+    }
+
+    tx.commit().await?;
+
+    for &id in &trashed {
+        state
+            .live
+            .publish(user.id, Change::Deleted, id, form.list_id);
+    }
+
+    let flash = trashed_flash(trashed.len());
+    items::reply(&state, &request, &session, form.list_id, &[], flash).await
+}
+
+/// Completes every open todo in a list, subtasks included.
+#[post("/todos/complete-all")]
+async fn complete_all(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
+    web::Form(form): web::Form<ListForm>,
+) -> Result<impl Responder, Error> {
+    if !user.owns_list(&state.db, form.list_id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    // Collected first, so the next occurrences of recurring todos stay open.
+    let open = sqlx::query_scalar::<_, u32>(
+        "
+            SELECT id FROM todos WHERE list_id=? AND NOT done AND deleted_at IS NULL
+        ",
+    )
+    .bind(form.list_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut changes = Vec::new();
+    for id in open {
+        if let Some((list_id, next)) = complete(&mut tx, id, user.id).await? {
+            changes.push((Change::Updated, id, list_id));
+            changes.extend(next.map(|next| (Change::Created, next, list_id)));
+        }
+    }
+
+    tx.commit().await?;
+
+    for (change, id, list_id) in changes {
+        state.live.publish(user.id, change, id, list_id);
+    }
+
+    items::reply(&state, &request, &session, form.list_id, &[], None).await
+}
+
+/// The form the todos' checkboxes belong to, and the buttons that act on a
+/// whole list.
+pub(crate) fn bulk_actions(list: &List, lists: &[List], csrf: &CsrfToken) -> Markup {
+    html! {
+        div class="bulk-actions" {
+            form id="bulk" action="/todos/bulk" method="POST" {
+                (csrf)
+                input type="hidden" name="list_id" value=(list.id);
+                "Selected:"
+                button name="action" value="complete" { "Complete" }
+                button name="action" value="delete" { "Delete" }
+                @if lists.len() > 1 {
+                    select name="to_list_id" aria-label="Move to list" {
+                        option value="" { "Move to…" }
+                        @for other in lists.iter().filter(|other| other.id != list.id) {
+                            option value=(other.id) { (other.name) }
+                        }
+                    }
+                    button name="action" value="move" { "Move" }
+                }
+            }
+            form action="/todos/complete-all" method="POST" {
+                (csrf)
+                input type="hidden" name="list_id" value=(list.id);
+                button { "Complete all" }
+            }
+            form action="/todos/clear-completed" method="POST" {
+                (csrf)
+                input type="hidden" name="list_id" value=(list.id);
+                button { "Clear completed" }
+            }
+        }
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(bulk)
+        .service(clear_completed)
+        .service(complete_all);
+}
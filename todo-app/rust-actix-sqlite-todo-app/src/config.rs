@@ -0,0 +1,199 @@
+use clap::Parser;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_DATABASE_URL: &str = "sqlite://todos.db";
+const DEFAULT_LOG_LEVEL: &str = "error";
+const DEFAULT_TRASH_MAX_AGE_DAYS: u32 = 30;
+
+/// The shortest `SESSION_KEY` cookies can be signed and encrypted with.
+const MIN_SESSION_KEY_LEN: usize = 64;
+
+/// The command line, and the environment variables that stand in for it.
+/// Settings left out here fall back to the file, then to the defaults.
+#[derive(Parser)]
+#[command(
+    about = None,
+    long_about = None,
+    after_help = "\
+The session key can only be set with SESSION_KEY or in the file, so it doesn't
+show up in the process list. Command-line options win over environment
+variables, which win over the file."
+)]
+struct Args {
+    /// Read settings from a TOML file
+    #[arg(long, env = "CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Address to listen on [default: 127.0.0.1]
+    #[arg(long, env = "HOST")]
+    host: Option<String>,
+    /// Port to listen on [default: 3000]
+    #[arg(long, env = "PORT")]
+    port: Option<u16>,
+    /// SQLite database [default: sqlite://todos.db]
+    #[arg(long, env = "DATABASE_URL", value_name = "URL")]
+    database_url: Option<String>,
+    /// env_logger filter, e.g. info or actix_web=debug; RUST_LOG works too
+    /// [default: error]
+    #[arg(long, env = "LOG_LEVEL", value_name = "FILTER")]
+    log_level: Option<String>,
+    /// Days before trashed todos are purged [default: 30]
+    #[arg(long, env = "TRASH_MAX_AGE_DAYS", value_name = "DAYS")]
+    trash_max_age_days: Option<u32>,
+    /// Print the settings in effect as TOML and exit
+    #[arg(long)]
+    print_config: bool,
+}
+
+/// The config file. Everything in it is optional.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct File {
+    host: Option<String>,
+    port: Option<u16>,
+    database_url: Option<String>,
+    log_level: Option<String>,
+    session_key: Option<String>,
+    trash_max_age_days: Option<u32>,
+}
+
+/// The server's settings.
+#[derive(Serialize)]
+pub(crate) struct Config {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) database_url: String,
+    pub(crate) log_level: String,
+    /// Signs and encrypts session cookies; a random one is used if unset.
+    #[serde(skip)]
+    pub(crate) session_key: Option<String>,
+    pub(crate) trash_max_age_days: u32,
+}
+
+/// What the command line asks for.
+pub(crate) enum Command {
+    Run(Config),
+    PrintConfig(Config),
+}
+
+#[derive(Debug, Display)]
+pub(crate) enum ConfigError {
+    /// Bad options, or `--help`, which clap reports itself.
+    #[display(fmt = "{}", _0)]
+    Args(clap::Error),
+    #[display(fmt = "invalid {}: {}", setting, message)]
+    Invalid { setting: String, message: String },
+    #[display(fmt = "couldn't read config file {}: {}", path, message)]
+    File { path: String, message: String },
+}
+
+/// Checks a session key from `setting`.
+fn session_key(setting: &str, key: String) -> Result<String, ConfigError> {
+    if key.len() < MIN_SESSION_KEY_LEN {
+        return Err(ConfigError::Invalid {
+            setting: setting.to_string(),
+            message: format!("must be at least {} bytes", MIN_SESSION_KEY_LEN),
+        });
+    }
+    Ok(key)
+}
+
+impl Config {
+    /// Reads the settings from the file named by `--config` (or `CONFIG`),
+    /// then the environment, then `args`, which start with the program name.
+    pub(crate) fn load(args: impl IntoIterator<Item = String>) -> Result<Command, ConfigError> {
+        let args = Args::try_parse_from(args).map_err(ConfigError::Args)?;
+
+        let file = match &args.config {
+            Some(path) => {
+                let at = |message: String| ConfigError::File {
+                    path: path.display().to_string(),
+                    message,
+                };
+                let toml = std::fs::read_to_string(path).map_err(|err| at(err.to_string()))?;
+                toml::from_str::<File>(&toml).map_err(|err| at(err.to_string()))?
+            }
+            None => File::default(),
+        };
+
+        let session_key = match std::env::var("SESSION_KEY") {
+            Ok(key) => Some(session_key("SESSION_KEY", key)?),
+            Err(_) => file
+                .session_key
+                .map(|key| session_key("session_key", key))
+                .transpose()?,
+        };
+
+        let config = Config {
+            host: args
+                .host
+                .or(file.host)
+                .unwrap_or_else(|| DEFAULT_HOST.to_string()),
+            port: args.port.or(file.port).unwrap_or(DEFAULT_PORT),
+            database_url: args
+                .database_url
+                .or(file.database_url)
+                .unwrap_or_else(|| DEFAULT_DATABASE_URL.to_string()),
+            log_level: args
+                .log_level
+                .or_else(|| std::env::var("RUST_LOG").ok())
+                .or(file.log_level)
+                .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string()),
+            session_key,
+            trash_max_age_days: args
+                .trash_max_age_days
+                .or(file.trash_max_age_days)
+                .unwrap_or(DEFAULT_TRASH_MAX_AGE_DAYS),
+        };
+
+        if args.print_config {
+            Ok(Command::PrintConfig(config))
+        } else {
+            Ok(Command::Run(config))
+        }
+    }
+
+    /// The settings as a TOML file, with the session key left out.
+    pub(crate) fn to_toml(&self) -> String {
+        let mut toml = toml::to_string(self).expect("settings are plain strings and numbers");
+        toml += match self.session_key {
+            Some(_) => "# session_key is set but not shown\n",
+            None => "# session_key isn't set; sessions end on restart\n",
+        };
+        toml
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printed_config_reads_back() {
+        let config = Config {
+            host: "::1".to_string(),
+            port: 8080,
+            database_url: "sqlite://C:\\todos\\\"mine\"\u{7f}\u{1}.db".to_string(),
+            log_level: "info,actix_web=debug".to_string(),
+            session_key: Some("k".repeat(MIN_SESSION_KEY_LEN)),
+            trash_max_age_days: 7,
+        };
+
+        let file: File = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(file.host.as_deref(), Some("::1"));
+        assert_eq!(file.port, Some(8080));
+        assert_eq!(file.database_url, Some(config.database_url));
+        assert_eq!(file.log_level.as_deref(), Some("info,actix_web=debug"));
+        assert_eq!(file.session_key, None);
+        assert_eq!(file.trash_max_age_days, Some(7));
+    }
+
+    #[test]
+    fn files_with_unknown_settings_are_rejected() {
+        assert!(toml::from_str::<File>("prot = 3000").is_err());
+        assert!(toml::from_str::<File>("port = \"many\"").is_err());
+    }
+}
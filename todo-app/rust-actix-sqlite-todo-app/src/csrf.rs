@@ -0,0 +1,211 @@
+use actix_multipart::Multipart;
+use actix_session::{Session, SessionExt};
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{header, Method},
+    web, FromRequest, HttpMessage, HttpRequest,
+};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use futures_util::future::LocalBoxFuture;
+use futures_util::StreamExt;
+use maud::{html, Markup, Render};
+use serde::Deserialize;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use crate::{multipart, transfer, Error};
+
+const TOKEN_KEY: &str = "csrf_token";
+
+/// The form field the token is submitted in.
+const FIELD: &str = "csrf_token";
+
+/// The header scripts can send the token in instead.
+const HEADER: &str = "X-CSRF-Token";
+
+/// The most the middleware reads of a form to find the token.
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+/// The most it reads of an upload to `/import`, which can carry the largest
+/// import.
+const MAX_IMPORT_BODY_SIZE: usize = transfer::MAX_IMPORT_SIZE + multipart::OVERHEAD;
+
+/// The session's CSRF token, which every form that changes something has to
+/// send back. Renders as the hidden field that does that.
+#[derive(Clone)]
+pub(crate) struct CsrfToken(String);
+
+impl CsrfToken {
+    /// The session's token, created if it doesn't have one yet.
+    pub(crate) fn from_session(session: &Session) -> Result<Self, Error> {
+        if let Some(token) = session
+            .get::<String>(TOKEN_KEY)
+            .map_err(|_| Error::Session)?
+        {
+            return Ok(CsrfToken(token));
+        }
+
+        let mut bytes = [0; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        session
+            .insert(TOKEN_KEY, &token)
+            .map_err(|_| Error::Session)?;
+        Ok(CsrfToken(token))
+    }
+
+    /// Compares in constant time, so the token can't be guessed byte by byte.
+    fn matches(&self, submitted: &str) -> bool {
+        self.0.len() == submitted.len()
+            && self
+                .0
+                .bytes()
+                .zip(submitted.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl Render for CsrfToken {
+    fn render(&self) -> Markup {
+        html! {
+            input type="hidden" name=(FIELD) value=(self.0);
+        }
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(CsrfToken::from_session(&req.get_session()))
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    csrf_token: Option<String>,
+}
+
+/// The token sent with a request: in the header, or in a url-encoded or
+/// multipart form.
+async fn submitted_token(req: &ServiceRequest, body: &web::Bytes) -> Option<String> {
+    if let Some(token) = req.headers().get(HEADER) {
+        return token.to_str().ok().map(str::to_string);
+    }
+
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    if content_type.starts_with("multipart/form-data") {
+        let limit = body.len();
+        let body = body.clone();
+        let multipart = Multipart::new(
+            req.headers(),
+            futures_util::stream::once(async move { Ok(body) }),
+        );
+        let mut fields = multipart::read_fields(multipart, limit).await?;
+        String::from_utf8(fields.remove(FIELD)?).ok()
+    } else {
+        serde_urlencoded::from_bytes::<TokenForm>(body)
+            .ok()?
+            .csrf_token
+    }
+}
+
+/// Reads the whole body, up to `limit` bytes, so the token can be looked for
+/// in it, and puts it back for the handler.
+async fn read_body(req: &mut ServiceRequest, limit: usize) -> Result<web::Bytes, PayloadError> {
+    let mut payload = req.take_payload();
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        body.extend_from_slice(&chunk?);
+        if body.len() > limit {
+            return Err(PayloadError::Overflow);
+        }
+    }
+
+    let body = body.freeze();
+    let replay = body.clone();
+    let stream: futures_util::stream::LocalBoxStream<'static, Result<web::Bytes, PayloadError>> =
+        Box::pin(futures_util::stream::once(async move { Ok(replay) }));
+    req.set_payload(Payload::from(stream));
+    Ok(body)
+}
+
+/// Rejects requests that change something unless they carry the session's
+/// CSRF token, so other sites can't submit our forms from a logged-in
+/// browser. Needs to be wrapped in the session middleware.
+///
+/// The JSON API is left alone: its bodies have to be JSON, which other sites
+/// can't send without the browser asking us first.
+pub(crate) struct Csrf;
+
+impl<S, B> Transform<S, ServiceRequest> for Csrf
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = CsrfMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub(crate) struct CsrfMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+            if safe || req.path().starts_with("/api/") {
+                return service.call(req).await;
+            }
+
+            let limit = match req.path() {
+                "/import" => MAX_IMPORT_BODY_SIZE,
+                _ => MAX_FORM_SIZE,
+            };
+            let body = read_body(&mut req, limit).await?;
+            let session = req.get_session();
+            let expected = session
+                .get::<String>(TOKEN_KEY)
+                .map_err(|_| Error::Session)?
+                .map(CsrfToken);
+
+            match (expected, submitted_token(&req, &body).await) {
+                (Some(expected), Some(submitted)) if expected.matches(&submitted) => {
+                    service.call(req).await
+                }
+                _ => Err(Error::Csrf.into()),
+            }
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use actix_session::Session;
+use serde::{Deserialize, Serialize};
+
+const FLASH_KEY: &str = "flash";
+
+/// A one-off message for the next page load, carried across a redirect in
+/// the session. The session is a cookie with room for about 4 KB, so todos
+/// are referred to by id, and their names looked up when the page is shown.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Flash {
+    /// Editing a todo failed; the page re-opens its edit form.
+    EditError { todo_id: u32, message: String },
+    /// A todo was moved to the trash; the page offers to undo it.
+    Deleted { todo_id: u32 },
+    /// Several todos were moved to the trash at once.
+    Trashed { count: usize },
+    /// Creating a todo failed.
+    NewTodoError { message: String },
+    /// A todo was completed while some of its subtasks weren't.
+    CompleteSubtasks { todo_id: u32, open: u32 },
+    /// A file of todos was imported.
+    Imported { count: usize },
+}
+
+pub(crate) fn set(session: &Session, flash: Flash) {
+    // Losing a flash message isn't worth failing the request over.
+    let _ = session.insert(FLASH_KEY, flash);
+}
+
+pub(crate) fn take(session: &Session) -> Option<Flash> {
+    session.remove_as::<Flash>(FLASH_KEY).and_then(Result::ok)
+}
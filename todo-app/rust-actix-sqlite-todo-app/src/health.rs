@@ -0,0 +1,35 @@
+use actix_web::{get, web, HttpResponse};
+use std::sync::Arc;
+
+use crate::State;
+
+/// Answers as long as the process is serving requests.
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Answers once the database can run a query, so load balancers only send
+/// traffic to instances that can serve it.
+#[get("/readyz")]
+async fn readyz(state: web::Data<Arc<State>>) -> HttpResponse {
+    let result = sqlx::query(
+        "
+            SELECT 1
+        ",
+    )
+    .execute(&state.db)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().body("ready"),
+        Err(err) => {
+            log::warn!("Not ready: {}", err);
+            HttpResponse::ServiceUnavailable().body("database unavailable")
+        }
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(healthz).service(readyz);
+}
@@ -0,0 +1,265 @@
+use actix_web::{get, web, Responder};
+use maud::{html, Markup};
+use sqlx::{Sqlite, SqliteConnection};
+use std::sync::Arc;
+
+use crate::{list_url, page, CurrentUser, Error, State, Todo};
+
+/// What happened to a todo; the `action` column of `todo_events`.
+#[derive(sqlx::Type, Clone, Copy, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+pub(crate) enum Action {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+    Purged,
+}
+
+/// Records `action` by `actor_id` on todo `todo_id`, along with its name.
+pub(crate) async fn record(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+    actor_id: Option<u32>,
+    action: Action,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+            INSERT INTO todo_events (todo_id, actor_id, action, after)
+            SELECT id, ?, ?, name FROM todos WHERE id=?
+        ",
+    )
+    .bind(actor_id)
+    .bind(action)
+    .bind(todo_id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Records a `purged` event for every todo matching the SQL `condition`,
+/// just before they're deleted for good. `?` in `condition` is bound to
+/// `value`.
+pub(crate) async fn record_purged<T>(
+    conn: &mut SqliteConnection,
+    actor_id: Option<u32>,
+    condition: &str,
+    value: T,
+) -> Result<(), sqlx::Error>
+where
+    T: for<'q> sqlx::Encode<'q, Sqlite> + sqlx::Type<Sqlite> + Send,
+{
+    let sql = format!(
+        "
+            INSERT INTO todo_events (todo_id, actor_id, action, after)
+            SELECT id, ?, 'purged', name FROM todos WHERE {}
+        ",
+        condition
+    );
+
+    sqlx::query(&sql)
+        .bind(actor_id)
+        .bind(value)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Records that `actor_id` changed `field` of todo `todo_id`.
+pub(crate) async fn record_change(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+    actor_id: u32,
+    field: &str,
+    before: Option<String>,
+    after: Option<String>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+            INSERT INTO todo_events (todo_id, actor_id, action, field, before, after)
+            VALUES (?, ?, 'updated', ?, ?, ?)
+        ",
+    )
+    .bind(todo_id)
+    .bind(actor_id)
+    .bind(field)
+    .bind(before)
+    .bind(after)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// The todo as it is now, tags included, for comparing with `record_changes`.
+pub(crate) async fn snapshot(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+) -> Result<Option<Todo>, sqlx::Error> {
+    let Some(mut todo) = sqlx::query_as::<_, Todo>(
+        "
+            SELECT * FROM todos WHERE id=?
+        ",
+    )
+    .bind(todo_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    todo.tags = sqlx::query_scalar::<_, String>(
+        "
+            SELECT tags.name FROM todo_tags JOIN tags ON tags.id = todo_tags.tag_id
+            WHERE todo_tags.todo_id=? ORDER BY tags.name
+        ",
+    )
+    .bind(todo_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Some(todo))
+}
+
+/// The fields whose changes are recorded, as stored in `todo_events`.
+/// Reordering isn't worth a history entry, so `position` is left out.
+fn fields(todo: &Todo) -> [(&'static str, Option<String>); 9] {
+    [
+        ("name", Some(todo.name.clone())),
+        ("done", Some(todo.done.to_string())),
+        ("priority", Some(todo.priority.name().to_string())),
+        ("due_date", todo.due_date.map(|date| date.to_string())),
+        (
+            "due_time",
+            todo.due_time.map(|time| time.format("%H:%M").to_string()),
+        ),
+        ("rrule", todo.rrule.clone()),
+        (
+            "tags",
+            Some(todo.tags.join(" ")).filter(|tags| !tags.is_empty()),
+        ),
+        ("list_id", Some(todo.list_id.to_string())),
+        ("parent_id", todo.parent_id.map(|id| id.to_string())),
+    ]
+}
+
+/// Records an `updated` event for every field that differs between two
+/// snapshots of the same todo.
+pub(crate) async fn record_changes(
+    conn: &mut SqliteConnection,
+    actor_id: u32,
+    before: &Todo,
+    after: &Todo,
+) -> Result<(), sqlx::Error> {
+    for ((field, old), (_, new)) in fields(before).into_iter().zip(fields(after)) {
+        if old != new {
+            record_change(conn, after.id, actor_id, field, old, new).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct Event {
+    action: Action,
+    field: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    created_at: String,
+    username: Option<String>,
+}
+
+impl Event {
+    fn describe(&self) -> Markup {
+        let or_none = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_string());
+
+        html! {
+            @match (self.action, self.field.as_deref()) {
+                (Action::Created, _) => { "Created “" (or_none(&self.after)) "”" }
+                (Action::Deleted, _) => "Moved to the trash",
+                (Action::Restored, _) => "Restored from the trash",
+                (Action::Purged, _) => "Deleted forever",
+                (Action::Updated, Some("name")) => {
+                    "Renamed from “" (or_none(&self.before)) "” to “" (or_none(&self.after)) "”"
+                }
+                (Action::Updated, Some("done")) if self.after.as_deref() == Some("true") => "Marked done",
+                (Action::Updated, Some("done")) => "Marked not done",
+                (Action::Updated, Some("list_id")) => "Moved to another list",
+                (Action::Updated, Some("parent_id")) => "Moved to another parent",
+                (Action::Updated, field) => {
+                    @let label = match field {
+                        Some("due_date") => "due date",
+                        Some("due_time") => "due time",
+                        Some("rrule") => "repeat rule",
+                        Some(field) => field,
+                        None => "something",
+                    };
+                    "Changed " (label) " from " (or_none(&self.before)) " to " (or_none(&self.after))
+                }
+            }
+        }
+    }
+}
+
+#[get("/todos/{id}/history")]
+async fn show_history(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !(user.owns_todo(&state.db, id).await? || user.owns_deleted_todo(&state.db, id).await?) {
+        return Err(Error::NotFound);
+    }
+
+    let (name, list_id) = sqlx::query_as::<_, (String, u32)>(
+        "
+            SELECT name, list_id FROM todos WHERE id=?
+        ",
+    )
+    .bind(id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let events = sqlx::query_as::<_, Event>(
+        "
+            SELECT todo_events.*, users.username FROM todo_events
+            LEFT JOIN users ON users.id = todo_events.actor_id
+            WHERE todo_events.todo_id=?
+            ORDER BY todo_events.id DESC
+        ",
+    )
+    .bind(id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(page(
+        "History",
+        html! {
+            nav class="lists" {
+                a href=(list_url(Some(list_id))) { "← Back to list" }
+            }
+            h1 { "History of “" (name) "”" }
+            @if events.is_empty() {
+                p class="search-summary" { "No changes have been recorded yet." }
+            }
+            ul class="history" {
+                @for event in &events {
+                    li {
+                        time datetime=(event.created_at) { (event.created_at.get(..16).unwrap_or(&event.created_at)) " UTC" }
+                        span class="actor" { (event.username.as_deref().unwrap_or("System")) }
+                        span { (event.describe()) }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(show_history);
+}
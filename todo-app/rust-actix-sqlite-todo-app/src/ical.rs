@@ -0,0 +1,387 @@
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use maud::html;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::csrf::CsrfToken;
+use crate::transfer::{load_records, Record};
+use crate::{list_url, page, recurrence, tags, CurrentUser, Error, Priority, State};
+
+/// Identifies the app in `PRODID` and makes todo `UID`s globally unique.
+const PRODUCT: &str = "rust-actix-sqlite-todo-app";
+
+pub(crate) const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+/// RFC 5545 priorities run from 1 (highest) to 9 (lowest); 0 means none.
+fn ical_priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::None => 0,
+        Priority::Low => 9,
+        Priority::Medium => 5,
+        Priority::High => 2,
+        Priority::Urgent => 1,
+    }
+}
+
+fn priority_from_ical(priority: u8) -> Priority {
+    match priority {
+        0 => Priority::None,
+        1 => Priority::Urgent,
+        2..=4 => Priority::High,
+        5 => Priority::Medium,
+        _ => Priority::Low,
+    }
+}
+
+fn uid(id: u32) -> String {
+    format!("todo-{}@{}", id, PRODUCT)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// Appends a content line, folded so no line is longer than 75 bytes.
+fn push_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// The `DTSTAMP` of todos written now.
+pub(crate) fn stamp() -> String {
+    Utc::now().format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The start of a calendar named `name`, up to its first VTODO.
+pub(crate) fn calendar_start(name: Option<&str>) -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, &format!("PRODID:-//{}//EN", PRODUCT));
+    if let Some(name) = name {
+        push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape(name)));
+    }
+    ics
+}
+
+/// Appends `record` as a VTODO. Due dates without a time are all-day; ones
+/// with a time are in the server's local time.
+pub(crate) fn push_todo(ics: &mut String, record: &Record, stamp: &str) {
+    push_line(ics, "BEGIN:VTODO");
+    if let Some(id) = record.id {
+        push_line(ics, &format!("UID:{}", uid(id)));
+    }
+    push_line(ics, &format!("DTSTAMP:{}", stamp));
+    push_line(ics, &format!("SUMMARY:{}", escape(&record.name)));
+    push_line(
+        ics,
+        if record.done {
+            "STATUS:COMPLETED"
+        } else {
+            "STATUS:NEEDS-ACTION"
+        },
+    );
+    if record.priority != Priority::None {
+        push_line(ics, &format!("PRIORITY:{}", ical_priority(record.priority)));
+    }
+    match (record.due_date, record.due_time) {
+        (Some(date), Some(time)) => push_line(
+            ics,
+            &format!("DUE:{}", date.and_time(time).format("%Y%m%dT%H%M%S")),
+        ),
+        (Some(date), None) => push_line(ics, &format!("DUE;VALUE=DATE:{}", date.format("%Y%m%d"))),
+        _ => {}
+    }
+    if let Some(rrule) = &record.rrule {
+        push_line(ics, &format!("RRULE:{}", rrule));
+    }
+    if !record.tags.is_empty() {
+        let categories: Vec<_> = record.tags.iter().map(|tag| escape(tag)).collect();
+        push_line(ics, &format!("CATEGORIES:{}", categories.join(",")));
+    }
+    if let Some(parent_id) = record.parent_id {
+        push_line(ics, &format!("RELATED-TO:{}", uid(parent_id)));
+    }
+    push_line(ics, "END:VTODO");
+}
+
+/// The end of a calendar, after its last VTODO.
+pub(crate) fn calendar_end() -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// Writes `records` as a calendar of VTODOs named `name`.
+pub(crate) fn to_ics(name: Option<&str>, records: &[Record]) -> String {
+    let stamp = stamp();
+
+    let mut ics = calendar_start(name);
+    for record in records {
+        push_todo(&mut ics, record, &stamp);
+    }
+    ics + &calendar_end()
+}
+
+/// Reads a `DUE` value: a date, a floating local time, or a UTC time.
+fn parse_due(value: &str) -> Option<(NaiveDate, Option<NaiveTime>)> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Some((date, None));
+    }
+
+    let due = match value.strip_suffix('Z') {
+        Some(utc) => {
+            let utc = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+            Utc.from_utc_datetime(&utc)
+                .with_timezone(&Local)
+                .naive_local()
+        }
+        None => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?,
+    };
+    Some((due.date(), Some(due.time())))
+}
+
+/// Reads the VTODOs in an iCalendar file; events and other components are
+/// ignored. `RELATED-TO` rebuilds subtasks when the parent comes first.
+/// Times with a `TZID` are read as local times, and repeat rules the app
+/// doesn't support are dropped.
+pub(crate) fn parse_ics(data: &str) -> Result<Vec<Record>, String> {
+    // Undo line folding.
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    if !lines
+        .iter()
+        .any(|line| line.eq_ignore_ascii_case("BEGIN:VCALENDAR"))
+    {
+        return Err("This isn't an iCalendar file".to_string());
+    }
+
+    let mut records = Vec::new();
+    let mut uids: HashMap<String, u32> = HashMap::new();
+    // The record being read, and how deep into components nested in it
+    // (like VALARM) we are.
+    let mut current: Option<(Record, u32)> = None;
+
+    for line in &lines {
+        let Some((name_and_params, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut params = name_and_params.split(';');
+        let name = params.next().unwrap_or_default().to_ascii_uppercase();
+        let value = value.trim();
+
+        match (&mut current, name.as_str()) {
+            (None, "BEGIN") if value.eq_ignore_ascii_case("VTODO") => {
+                current = Some((Record::default(), 0));
+            }
+            (None, _) => {}
+            (Some((_, depth)), "BEGIN") => *depth += 1,
+            (Some((_, depth)), "END") if *depth > 0 => *depth -= 1,
+            (Some(_), "END") => {
+                if let Some((mut record, _)) = current.take() {
+                    record.id = Some(records.len() as u32 + 1);
+                    records.push(record);
+                }
+            }
+            (Some((_, depth)), _) if *depth > 0 => {}
+            (Some((record, _)), name) => match name {
+                "UID" => {
+                    uids.insert(value.to_string(), records.len() as u32 + 1);
+                }
+                "SUMMARY" => record.name = unescape(value).replace('\n', " "),
+                "STATUS" => record.done = value.eq_ignore_ascii_case("COMPLETED"),
+                "COMPLETED" => record.done = true,
+                "PRIORITY" => {
+                    record.priority = priority_from_ical(value.parse().unwrap_or_default())
+                }
+                "DUE" => {
+                    let (date, time) =
+                        parse_due(value).ok_or_else(|| format!("Invalid due date “{}”", value))?;
+                    record.due_date = Some(date);
+                    record.due_time = time;
+                }
+                "RRULE" => record.rrule = recurrence::parse_rrule(value).ok().flatten(),
+                "CATEGORIES" => record.tags.extend(tags::parse_tags(&unescape(value))),
+                "RELATED-TO"
+                    if params.all(|param| {
+                        !param.to_ascii_uppercase().starts_with("RELTYPE=")
+                            || param.eq_ignore_ascii_case("RELTYPE=PARENT")
+                    }) =>
+                {
+                    record.parent_id = uids.get(value).copied();
+                }
+                _ => {}
+            },
+        }
+    }
+
+    Ok(records)
+}
+
+/// The list's feed token, created if it doesn't have one yet.
+async fn feed_token(state: &State, list_id: u32) -> Result<String, sqlx::Error> {
+    sqlx::query(
+        "
+            UPDATE lists SET feed_token = lower(hex(randomblob(16)))
+            WHERE id=? AND feed_token IS NULL
+        ",
+    )
+    .bind(list_id)
+    .execute(&state.db)
+    .await?;
+
+    sqlx::query_scalar::<_, String>(
+        "
+            SELECT feed_token FROM lists WHERE id=?
+        ",
+    )
+    .bind(list_id)
+    .fetch_one(&state.db)
+    .await
+}
+
+/// Shows the list's feed URL for calendar clients to subscribe to.
+#[get("/lists/{id}/calendar")]
+async fn show_calendar(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    csrf: CsrfToken,
+    request: HttpRequest,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_list(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let name = sqlx::query_scalar::<_, String>(
+        "
+            SELECT name FROM lists WHERE id=?
+        ",
+    )
+    .bind(id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let path = format!("/calendar/{}.ics", feed_token(&state, id).await?);
+    let connection = request.connection_info();
+    let url = format!("{}://{}{}", connection.scheme(), connection.host(), path);
+
+    Ok(page(
+        "Calendar feed",
+        html! {
+            nav class="lists" {
+                a href=(list_url(Some(id))) { "← Back to list" }
+            }
+            h1 { "Calendar feed for “" (name) "”" }
+            p class="search-summary" {
+                "Subscribe to this address in a calendar app to see the list's todos. "
+                "Anyone with the address can read them."
+            }
+            form class="credentials" {
+                input readonly value=(url) aria-label="Feed address" onfocus="this.select()";
+            }
+            nav class="views" {
+                a href=(path) download="todos.ics" { "Download .ics" }
+                form action=(format!("/lists/{}/calendar/reset", id)) method="POST" {
+                    (csrf)
+                    button { "Change address" }
+                }
+            }
+        },
+    ))
+}
+
+/// Replaces the list's feed token, so the old address stops working.
+#[post("/lists/{id}/calendar/reset")]
+async fn reset_calendar(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_list(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    sqlx::query(
+        "
+            UPDATE lists SET feed_token = NULL WHERE id=?
+        ",
+    )
+    .bind(id)
+    .execute(&state.db)
+    .await?;
+
+    Ok(web::Redirect::to(format!("/lists/{}/calendar", id)).see_other())
+}
+
+/// The list's todos as an iCalendar file. Needs no session; the token is the
+/// password.
+#[get("/calendar/{token}.ics")]
+async fn feed(
+    state: web::Data<Arc<State>>,
+    token: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let list = sqlx::query_as::<_, (u32, Option<u32>, String)>(
+        "
+            SELECT id, user_id, name FROM lists WHERE feed_token=?
+        ",
+    )
+    .bind(token.into_inner())
+    .fetch_optional(&state.db)
+    .await?;
+
+    let Some((list_id, Some(user_id), name)) = list else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let records = load_records(&state.db, user_id, Some(list_id)).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, CONTENT_TYPE))
+        .body(to_ics(Some(&name), &records)))
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(show_calendar)
+        .service(reset_calendar)
+        .service(feed);
+}
@@ -0,0 +1,522 @@
+use actix_session::Session;
+use actix_web::{web, HttpRequest};
+use chrono::{Local, NaiveDateTime};
+use maud::{html, Markup};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+
+use crate::csrf::CsrfToken;
+use crate::flash::{self, Flash};
+use crate::{
+    list_url, priority_select, recurrence, subtasks, tags, urlencode, Cursor, Error, List,
+    ListQuery, Priority, Sort, State, Status, Todo, View,
+};
+
+/// The todos of a list as the list page shows them: one page of top-level
+/// todos, with their subtasks.
+pub(crate) struct ListItems {
+    list_id: u32,
+    todos: Vec<Todo>,
+    progress: HashMap<u32, subtasks::Progress>,
+    now: NaiveDateTime,
+    /// Positions are relative to all of a todo's siblings, so reordering is
+    /// only offered when the whole list is shown in manual order.
+    pub(crate) reorderable: bool,
+    /// How many top-level todos come before this page, and how many there
+    /// are on all pages.
+    pub(crate) offset: usize,
+    pub(crate) total_roots: usize,
+    pub(crate) prev: Option<Cursor>,
+    pub(crate) next: Option<Cursor>,
+    /// How many of the list's todos aren't done, whatever the filters.
+    pub(crate) remaining: u32,
+}
+
+/// The todos a list page shows (`shown`), and those of them shown at the top
+/// level (`roots`). Binds `?1` to `?6`.
+const SHOWN: &str = "
+    shown AS (
+        SELECT * FROM todos
+        WHERE list_id = ?1 AND deleted_at IS NULL AND CASE ?2
+            WHEN 'today' THEN due_date = ?3
+            WHEN 'upcoming' THEN due_date > ?3
+            WHEN 'overdue' THEN NOT done
+                AND (due_date < ?3 OR (due_date = ?3 AND due_time < ?4))
+            ELSE true
+        END
+        AND (?5 IS NULL OR id IN (
+            SELECT todo_id FROM todo_tags JOIN tags ON tags.id = todo_tags.tag_id
+            WHERE tags.name = ?5
+        ))
+        AND CASE ?6
+            WHEN 'active' THEN NOT done
+            WHEN 'completed' THEN done
+            ELSE true
+        END
+    ),
+    roots AS (
+        SELECT * FROM shown
+        WHERE parent_id IS NULL OR parent_id NOT IN (SELECT id FROM shown)
+    )
+";
+
+impl ListItems {
+    pub(crate) async fn load(
+        db: &SqlitePool,
+        list: &List,
+        query: &ListQuery,
+    ) -> Result<Self, sqlx::Error> {
+        let now = Local::now().naive_local();
+
+        // Manual order only makes sense for the whole list; the due-date views
+        // fall back to sorting by due date.
+        let sort = match list.sort {
+            Sort::Manual if query.view != View::All => Sort::Due,
+            sort => sort,
+        };
+        let keys = format!("({})", sort.keys().join(", "));
+        let ascending = sort.keys().join(", ");
+        let descending: Vec<String> = sort
+            .keys()
+            .iter()
+            .map(|key| format!("{} DESC", key))
+            .collect();
+
+        // A page starts after (or ends before) the top-level todo in the
+        // cursor, found by comparing sort keys, so it doesn't matter how far
+        // into the list it is. "Previous" pages are read backwards.
+        let (cursor_id, page_roots) = match query.page {
+            Some(Cursor::After(id)) => (Some(id), format!(
+                "SELECT id FROM roots WHERE {keys} > (SELECT {ascending} FROM todos WHERE id = ?7) ORDER BY {ascending} LIMIT ?8"
+            )),
+            Some(Cursor::Before(id)) => (Some(id), format!(
+                "SELECT id FROM roots WHERE {keys} < (SELECT {ascending} FROM todos WHERE id = ?7) ORDER BY {} LIMIT ?8",
+                descending.join(", ")
+            )),
+            None => (None, format!("SELECT id FROM roots ORDER BY {ascending} LIMIT ?8")),
+        };
+
+        let mut todos = sqlx::query_as::<_, Todo>(&format!(
+            "
+                WITH RECURSIVE {SHOWN},
+                page_roots AS ({page_roots}),
+                page(id) AS (
+                    SELECT id FROM page_roots
+                    UNION ALL
+                    SELECT shown.id FROM shown JOIN page ON shown.parent_id = page.id
+                )
+                SELECT * FROM shown WHERE id IN (SELECT id FROM page)
+                ORDER BY {ascending}
+            "
+        ))
+        .bind(list.id)
+        .bind(query.view.name())
+        .bind(now.date())
+        .bind(now.time())
+        .bind(&query.tag)
+        .bind(query.status.name())
+        .bind(cursor_id)
+        .bind(query.per_page())
+        .fetch_all(db)
+        .await?;
+
+        // The todo in the cursor is gone, or there's nothing past it any more.
+        if todos.is_empty() && query.page.is_some() {
+            let first_page = ListQuery {
+                page: None,
+                ..query.clone()
+            };
+            return Box::pin(Self::load(db, list, &first_page)).await;
+        }
+
+        tags::load_tags(db, &mut todos).await?;
+
+        let ids: HashSet<u32> = todos.iter().map(|todo| todo.id).collect();
+        let roots: Vec<u32> = todos
+            .iter()
+            .filter(|todo| {
+                !todo
+                    .parent_id
+                    .is_some_and(|parent_id| ids.contains(&parent_id))
+            })
+            .map(|todo| todo.id)
+            .collect();
+
+        let (total_roots, offset, remaining) = sqlx::query_as::<_, (u32, u32, u32)>(&format!(
+            "
+                WITH {SHOWN}
+                SELECT
+                    (SELECT COUNT(*) FROM roots),
+                    (SELECT COUNT(*) FROM roots
+                        WHERE {keys} < (SELECT {ascending} FROM todos WHERE id = ?7)),
+                    (SELECT COUNT(*) FROM todos
+                        WHERE list_id = ?1 AND deleted_at IS NULL AND NOT done)
+            "
+        ))
+        .bind(list.id)
+        .bind(query.view.name())
+        .bind(now.date())
+        .bind(now.time())
+        .bind(&query.tag)
+        .bind(query.status.name())
+        .bind(roots.first())
+        .fetch_one(db)
+        .await?;
+
+        let (offset, total_roots) = (offset as usize, total_roots as usize);
+        let prev = roots
+            .first()
+            .filter(|_| offset > 0)
+            .map(|id| Cursor::Before(*id));
+        let next = roots
+            .last()
+            .filter(|_| offset + roots.len() < total_roots)
+            .map(|id| Cursor::After(*id));
+
+        Ok(ListItems {
+            list_id: list.id,
+            progress: subtasks::load_progress(db, list.id).await?,
+            todos,
+            now,
+            reorderable: query.view == View::All
+                && query.tag.is_none()
+                && query.status == Status::All
+                && sort == Sort::Manual,
+            offset,
+            total_roots,
+            prev,
+            next,
+            remaining,
+        })
+    }
+
+    /// The top-level todos, and what `todo_item` needs to render them with
+    /// their subtasks. Subtasks whose parent is filtered out are shown at the
+    /// top level.
+    pub(crate) fn tree<'a>(
+        &'a self,
+        flash: Option<&'a Flash>,
+        csrf: &'a CsrfToken,
+    ) -> (Vec<&'a Todo>, ItemContext<'a>) {
+        let ids: HashSet<u32> = self.todos.iter().map(|todo| todo.id).collect();
+        let mut roots = Vec::new();
+        let mut children: HashMap<u32, Vec<&Todo>> = HashMap::new();
+        for todo in &self.todos {
+            match todo.parent_id.filter(|parent_id| ids.contains(parent_id)) {
+                Some(parent_id) => children.entry(parent_id).or_default().push(todo),
+                None => roots.push(todo),
+            }
+        }
+
+        let ctx = ItemContext {
+            list_id: self.list_id,
+            now: self.now,
+            reorderable: self.reorderable,
+            flash,
+            csrf,
+            children,
+            progress: &self.progress,
+        };
+
+        (roots, ctx)
+    }
+
+    /// The top-level todo that `todo_id` is shown under, if it's shown at all.
+    fn root_of(&self, mut todo_id: u32) -> Option<u32> {
+        let parents: HashMap<u32, Option<u32>> = self
+            .todos
+            .iter()
+            .map(|todo| (todo.id, todo.parent_id))
+            .collect();
+
+        loop {
+            match parents.get(&todo_id)? {
+                Some(parent_id) if parents.contains_key(parent_id) => todo_id = *parent_id,
+                _ => return Some(todo_id),
+            }
+        }
+    }
+}
+
+/// Everything `todo_item` needs to know besides the todo itself.
+pub(crate) struct ItemContext<'a> {
+    list_id: u32,
+    now: NaiveDateTime,
+    reorderable: bool,
+    flash: Option<&'a Flash>,
+    csrf: &'a CsrfToken,
+    children: HashMap<u32, Vec<&'a Todo>>,
+    progress: &'a HashMap<u32, subtasks::Progress>,
+}
+
+/// Renders `todo` and its subtasks as the `i`th of `siblings` todos.
+pub(crate) fn todo_item(todo: &Todo, i: usize, siblings: usize, ctx: &ItemContext) -> Markup {
+    let edit_error = match ctx.flash {
+        Some(Flash::EditError { todo_id, message }) if *todo_id == todo.id => Some(message),
+        _ => None,
+    };
+
+    html! {
+        li.completed[todo.done].overdue[todo.is_overdue(ctx.now)] data-id=(todo.id) draggable=[ctx.reorderable.then_some("true")] {
+            @if ctx.reorderable {
+                @let (up, down) = (i.saturating_sub(1), i + 1);
+                div class="reorder" {
+                    form action=(format!("/todos/{}/move", todo.id)) method="POST" {
+                        (ctx.csrf)
+                        input type="hidden" name="position" value=(up);
+                        button aria-label="Move up" disabled[i == 0] { "▲" }
+                    }
+                    form action=(format!("/todos/{}/move", todo.id)) method="POST" {
+                        (ctx.csrf)
+                        input type="hidden" name="position" value=(down);
+                        button aria-label="Move down" disabled[down == siblings] { "▼" }
+                    }
+                }
+            }
+            input type="checkbox" class="select" name="ids" value=(todo.id) form="bulk" aria-label="Select todo";
+            form action=(format!("/todos/{}/delete", todo.id)) method="POST" {
+                (ctx.csrf)
+                button class="delete" aria-label="Delete todo" {
+                    "❌"
+                }
+            }
+            form action=(format!("/todos/{}/toggle-completion", todo.id)) method="POST" {
+                (ctx.csrf)
+                button class="text" {
+                    (todo.name)
+                }
+            }
+            @if todo.priority != Priority::None {
+                span class=(format!("priority {}", todo.priority.name())) { (todo.priority.label()) }
+            }
+            @if let Some(rule) = todo.rrule.as_deref().and_then(|rrule| rrule.parse::<recurrence::Rule>().ok()) {
+                span class="recurrence" title=(rule) { "🔁 " (rule.describe()) }
+            }
+            @if let Some(progress) = ctx.progress.get(&todo.id) {
+                span class="progress" title="Subtasks done" { (progress.done) "/" (progress.total) }
+            }
+            @for tag in &todo.tags {
+                a class="tag" href=(format!("/lists/{}?tag={}", ctx.list_id, urlencode(tag))) { (tag) }
+            }
+            details.inline-edit open[edit_error.is_some()] {
+                summary aria-label="Edit todo" { "✏️" }
+                form action=(format!("/todos/{}/edit", todo.id)) method="POST" {
+                    (ctx.csrf)
+                    input name="name" value=(todo.name) aria-label="Todo name" required;
+                    (priority_select(todo.priority))
+                    input name="rrule" value=[todo.rrule.as_deref()] placeholder="Repeat rule" aria-label="Repeat rule" list="rrules";
+                    button { "Save" }
+                }
+                @if let Some(message) = edit_error {
+                    p class="error" { (message) }
+                }
+            }
+            details class="inline-edit" {
+                summary aria-label="Edit tags" { "🏷" }
+                form action=(format!("/todos/{}/tags", todo.id)) method="POST" {
+                    (ctx.csrf)
+                    input name="tags" value=(todo.tags.join(" ")) placeholder="@phone @office" aria-label="Tags";
+                }
+            }
+            details class="inline-edit" {
+                summary aria-label="Add subtask" { "➕" }
+                form action="/todos/new" method="POST" {
+                    (ctx.csrf)
+                    input type="hidden" name="list_id" value=(todo.list_id);
+                    input type="hidden" name="parent_id" value=(todo.id);
+                    input name="name" placeholder="New subtask..." aria-label="Subtask name" required;
+                }
+            }
+            a class="history-link" href=(format!("/todos/{}/history", todo.id)) aria-label="History" title="History" { "🕘" }
+            @if let Some(due_date) = todo.due_date {
+                time class="due" datetime=(due_date) {
+                    (due_date.format("%a %-d %b %Y"))
+                    @if let Some(due_time) = todo.due_time {
+                        " " (due_time.format("%H:%M"))
+                    }
+                }
+            }
+            @if let Some(children) = ctx.children.get(&todo.id) {
+                ul class="subtasks" {
+                    @for (i, child) in children.iter().enumerate() {
+                        (todo_item(child, i, children.len(), ctx))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The name of the todo `flash` is about, if it's about one that still
+/// exists.
+pub(crate) async fn flash_todo_name(
+    db: &SqlitePool,
+    flash: Option<&Flash>,
+) -> Result<Option<String>, sqlx::Error> {
+    let todo_id = match flash {
+        Some(Flash::Deleted { todo_id } | Flash::CompleteSubtasks { todo_id, .. }) => *todo_id,
+        _ => return Ok(None),
+    };
+
+    sqlx::query_scalar::<_, String>(
+        "
+            SELECT name FROM todos WHERE id=?
+        ",
+    )
+    .bind(todo_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// The banners and errors shown above a list, with `name` from
+/// `flash_todo_name`. Edit errors are shown on the todo instead.
+pub(crate) fn notices(flash: Option<&Flash>, name: Option<&str>, csrf: &CsrfToken) -> Markup {
+    html! {
+        div class="notices" {
+            @match (flash, name) {
+                (Some(Flash::CompleteSubtasks { todo_id, open }), Some(name)) => {
+                    div class="banner" {
+                        "“" (name) "” still has " (open) " unfinished subtask" @if *open != 1 { "s" } "."
+                        form action=(format!("/todos/{}/complete-subtasks", todo_id)) method="POST" {
+                            (csrf)
+                            button { "Complete them too" }
+                        }
+                    }
+                }
+                (Some(Flash::Deleted { todo_id }), Some(name)) => {
+                    div class="banner" {
+                        "Deleted “" (name) "”."
+                        form action=(format!("/todos/{}/restore", todo_id)) method="POST" {
+                            (csrf)
+                            button { "Undo" }
+                        }
+                    }
+                }
+                (Some(Flash::Trashed { count }), _) => {
+                    div class="banner" {
+                        "Moved " (count) " todo" @if *count != 1 { "s" } " to the trash."
+                        a href="/trash" { "Open the trash" }
+                    }
+                }
+                (Some(Flash::Imported { count }), _) => {
+                    div class="banner" {
+                        "Imported " (count) " todo" @if *count != 1 { "s" } "."
+                    }
+                }
+                (Some(Flash::NewTodoError { message }), _) => p class="error" { (message) },
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The counter of todos left, the status tabs and the links to other pages,
+/// shown under the list.
+pub(crate) fn footer(items: &ListItems, query: &ListQuery) -> Markup {
+    let with = |status, page| {
+        ListQuery {
+            status,
+            page,
+            ..query.clone()
+        }
+        .url(items.list_id)
+    };
+
+    html! {
+        footer class="list-footer" {
+            span class="remaining" {
+                (items.remaining) " item" @if items.remaining != 1 { "s" } " left"
+            }
+            nav class="views" {
+                @for status in Status::ALL {
+                    a.current[status == query.status] href=(with(status, None)) { (status.label()) }
+                }
+            }
+            @if items.prev.is_some() || items.next.is_some() {
+                nav class="pages" {
+                    @if let Some(prev) = items.prev {
+                        a href=(with(query.status, Some(prev))) rel="prev" { "← Previous" }
+                    }
+                    @if let Some(next) = items.next {
+                        a href=(with(query.status, Some(next))) rel="next" { "Next →" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether the page asked for just the changed part of itself, the way htmx
+/// does.
+fn is_fragment_request(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get("HX-Request")
+        .is_some_and(|value| value == "true")
+}
+
+/// The filters and page of the page a fragment is for, from the
+/// `HX-Current-URL` header.
+fn page_query(request: &HttpRequest) -> ListQuery {
+    request
+        .headers()
+        .get("HX-Current-URL")
+        .and_then(|url| url.to_str().ok())
+        .and_then(|url| url.split_once('?'))
+        .and_then(|(_, query)| web::Query::<ListQuery>::from_query(query).ok())
+        .map_or_else(ListQuery::default, web::Query::into_inner)
+}
+
+/// Answers a form that changed todos `changed` in list `list_id`. A fragment
+/// request gets the notices, the top-level items the changed todos are shown
+/// under and the list's footer, to swap into the page; anything else is sent back to the
+/// list, with `flash` in the session.
+pub(crate) async fn reply(
+    state: &State,
+    request: &HttpRequest,
+    session: &Session,
+    list_id: u32,
+    changed: &[u32],
+    flash: Option<Flash>,
+) -> Result<web::Either<web::Redirect, Markup>, Error> {
+    if !is_fragment_request(request) {
+        if let Some(flash) = flash {
+            flash::set(session, flash);
+        }
+        return Ok(web::Either::Left(
+            web::Redirect::to(list_url(Some(list_id))).see_other(),
+        ));
+    }
+
+    let list = sqlx::query_as::<_, List>(
+        "
+            SELECT id, name, sort FROM lists WHERE id=?
+        ",
+    )
+    .bind(list_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let query = page_query(request);
+    let items = ListItems::load(&state.db, &list, &query).await?;
+    let csrf = CsrfToken::from_session(session)?;
+    let (roots, ctx) = items.tree(flash.as_ref(), &csrf);
+    let name = flash_todo_name(&state.db, flash.as_ref()).await?;
+
+    let mut shown: Vec<u32> = Vec::new();
+    for root_id in changed.iter().filter_map(|id| items.root_of(*id)) {
+        if !shown.contains(&root_id) {
+            shown.push(root_id);
+        }
+    }
+
+    Ok(web::Either::Right(html! {
+        (notices(flash.as_ref(), name.as_deref(), &csrf))
+        @for (i, todo) in roots.iter().enumerate() {
+            @if shown.contains(&todo.id) {
+                (todo_item(todo, items.offset + i, items.total_roots, &ctx))
+            }
+        }
+        (footer(&items, &query))
+    }))
+}
@@ -0,0 +1,170 @@
+use actix_session::Session;
+use actix_web::{get, post, web, Responder};
+use maud::Markup;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::csrf::CsrfToken;
+use crate::{flash, history, render_list, CurrentUser, Error, ListQuery, Sort, State};
+
+#[get("/lists/{id}")]
+async fn show_list(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    csrf: CsrfToken,
+    id: web::Path<u32>,
+    web::Query(query): web::Query<ListQuery>,
+) -> Result<Markup, Error> {
+    render_list(
+        &state,
+        &user,
+        Some(id.into_inner()),
+        &query,
+        flash::take(&session),
+        &csrf,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct ListForm {
+    name: String,
+}
+
+impl ListForm {
+    fn name(&self) -> Result<&str, Error> {
+        match self.name.trim() {
+            "" => Err(Error::Validation("A list needs a name".to_string())),
+            name => Ok(name),
+        }
+    }
+}
+
+#[post("/lists/new")]
+async fn new_list(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    web::Form(form): web::Form<ListForm>,
+) -> Result<impl Responder, Error> {
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+            INSERT INTO lists (name, user_id) VALUES (?, ?) RETURNING id
+        ",
+    )
+    .bind(form.name()?)
+    .bind(user.id)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(web::Redirect::to(format!("/lists/{}", id)).see_other())
+}
+
+#[post("/lists/{id}/rename")]
+async fn rename_list(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+    web::Form(form): web::Form<ListForm>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_list(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let updated = sqlx::query(
+        "
+            UPDATE lists SET name=? WHERE id=?
+        ",
+    )
+    .bind(form.name()?)
+    .bind(id)
+    .execute(&state.db)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(web::Redirect::to(format!("/lists/{}", id)).see_other())
+}
+
+#[derive(Deserialize)]
+struct SortForm {
+    sort: Sort,
+}
+
+/// Remembers how list `id` should be sorted.
+#[post("/lists/{id}/sort")]
+async fn sort_list(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+    web::Form(form): web::Form<SortForm>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_list(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let updated = sqlx::query(
+        "
+            UPDATE lists SET sort=? WHERE id=?
+        ",
+    )
+    .bind(form.sort)
+    .bind(id)
+    .execute(&state.db)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    Ok(web::Redirect::to(format!("/lists/{}", id)).see_other())
+}
+
+/// Deletes a list along with all of its todos.
+#[post("/lists/{id}/delete")]
+async fn delete_list(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_list(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    history::record_purged(&mut tx, Some(user.id), "list_id = ?", id).await?;
+
+    let deleted = sqlx::query(
+        "
+            DELETE FROM lists WHERE id=?
+        ",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    if deleted.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    tx.commit().await?;
+
+    Ok(web::Redirect::to("/").see_other())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(show_list)
+        .service(new_list)
+        .service(rename_list)
+        .service(sort_list)
+        .service(delete_list);
+}
@@ -0,0 +1,112 @@
+use actix_web::{get, rt, web, HttpResponse};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{CurrentUser, State};
+
+/// How many changes a slow subscriber can fall behind before it's told to
+/// start over.
+const CAPACITY: usize = 256;
+
+/// How often idle streams get a comment, so proxies don't time them out and
+/// closed connections are noticed.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Change {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Change {
+    fn name(self) -> &'static str {
+        match self {
+            Change::Created => "created",
+            Change::Updated => "updated",
+            Change::Deleted => "deleted",
+        }
+    }
+}
+
+/// A change to one of `user_id`'s todos. Only the ids are sent, and the page
+/// fetches whatever it needs to show.
+#[derive(Serialize, Clone)]
+struct TodoChange {
+    #[serde(skip)]
+    user_id: u32,
+    #[serde(skip)]
+    change: Change,
+    todo_id: u32,
+    list_id: u32,
+}
+
+/// Fans changes to todos out to every open `/events` stream.
+pub(crate) struct Broadcaster {
+    sender: broadcast::Sender<TodoChange>,
+}
+
+impl Broadcaster {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Broadcaster { sender }
+    }
+
+    /// Tells `user_id`'s open pages that todo `todo_id` in list `list_id`
+    /// changed. Call it once the change is committed.
+    pub(crate) fn publish(&self, user_id: u32, change: Change, todo_id: u32, list_id: u32) {
+        // Sending only fails when nobody is listening.
+        let _ = self.sender.send(TodoChange {
+            user_id,
+            change,
+            todo_id,
+            list_id,
+        });
+    }
+}
+
+/// A Server-Sent Events stream of changes to the user's todos, with events
+/// named `created`, `updated` and `deleted`. A `resync` event means some
+/// changes were missed.
+#[get("/events")]
+async fn events(state: web::Data<Arc<State>>, user: CurrentUser) -> HttpResponse {
+    let receiver = state.live.sender.subscribe();
+    let keep_alive = rt::time::interval(KEEP_ALIVE_INTERVAL);
+
+    let stream = futures_util::stream::unfold(
+        (receiver, keep_alive),
+        move |(mut receiver, mut keep_alive)| async move {
+            let message = loop {
+                tokio::select! {
+                    change = receiver.recv() => match change {
+                        Ok(change) if change.user_id != user.id => continue,
+                        Ok(change) => {
+                            let data = serde_json::to_string(&change).ok()?;
+                            break format!("event: {}\ndata: {}\n\n", change.change.name(), data);
+                        }
+                        Err(RecvError::Lagged(_)) => break "event: resync\ndata: {}\n\n".to_string(),
+                        Err(RecvError::Closed) => return None,
+                    },
+                    _ = keep_alive.tick() => break ": keep-alive\n\n".to_string(),
+                }
+            };
+
+            Some((
+                Ok::<_, actix_web::Error>(web::Bytes::from(message)),
+                (receiver, keep_alive),
+            ))
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(events);
+}
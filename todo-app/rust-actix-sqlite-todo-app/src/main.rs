@@ -1,49 +1,485 @@
-use actix_web::{error, get, middleware::Logger, post, web, App, HttpServer, Responder};
+use actix_session::{storage::CookieSessionStore, Session, SessionMiddleware};
+use actix_web::{
+    body::MessageBody,
+    cookie::Key,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    error, get,
+    http::{header, header::ContentType, StatusCode},
+    middleware::Logger,
+    post, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
 use derive_more::{Display, Error};
-use maud::{html, Markup, DOCTYPE};
-use serde::Deserialize;
-use sqlx::SqlitePool;
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use std::process::ExitCode;
+use std::str::FromStr;
 use std::sync::Arc;
 
+mod api;
+mod auth;
+mod bulk;
+mod config;
+mod csrf;
+mod flash;
+mod health;
+mod history;
+mod ical;
+mod items;
+mod lists;
+mod live;
+mod metrics;
+mod multipart;
+mod recurrence;
+mod reorder;
+mod search;
+mod subtasks;
+mod tags;
+#[cfg(test)]
+mod tests;
+mod transfer;
+mod trash;
+
+use auth::CurrentUser;
+use config::{Command, Config, ConfigError};
+use csrf::CsrfToken;
+use flash::Flash;
+use live::Change;
+
 struct State {
     db: SqlitePool,
+    live: live::Broadcaster,
+    metrics: metrics::Metrics,
+}
+
+impl State {
+    fn new(db: SqlitePool) -> Self {
+        State {
+            db,
+            live: live::Broadcaster::new(),
+            metrics: metrics::Metrics::new(),
+        }
+    }
 }
 
 #[derive(Debug, Display, Error)]
 enum Error {
     Sqlx(sqlx::Error),
+    Migrate(sqlx::migrate::MigrateError),
+    #[display(fmt = "That doesn't exist, or not any more")]
+    NotFound,
+    /// Input that can't be used, with a message saying why.
+    #[display(fmt = "{}", _0)]
+    Validation(#[error(not(source))] String),
+    #[display(fmt = "You need to log in")]
+    Unauthorized,
+    #[display(fmt = "That belongs to another user")]
+    Forbidden,
+    /// A form without the session's CSRF token; see `csrf::Csrf`.
+    #[display(fmt = "This form has expired. Go back, reload the page and try again.")]
+    Csrf,
+    #[display(fmt = "Failed to hash password")]
+    PasswordHash,
+    #[display(fmt = "Failed to update session")]
+    Session,
+    #[display(fmt = "Failed to export todos")]
+    Export,
+}
+
+impl Error {
+    /// What to tell the user. Server errors are logged rather than shown.
+    fn message(&self) -> String {
+        if error::ResponseError::status_code(self).is_server_error() {
+            log::error!("{}", self);
+            "Something went wrong on our end. Please try again.".to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+/// Errors as the HTML pages show them. The API wraps them in
+/// `api::ApiError` to answer with JSON instead.
+impl error::ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Forbidden | Error::Csrf => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            // Browsers are better off at the login form than at a 401.
+            Error::Unauthorized => HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, "/login"))
+                .finish(),
+            _ => HttpResponse::build(self.status_code())
+                .insert_header(ContentType::html())
+                .body(error_page(self.status_code(), &self.message()).into_string()),
+        }
+    }
 }
 
-impl error::ResponseError for Error {}
+fn error_page(status: StatusCode, message: &str) -> Markup {
+    let title = status.canonical_reason().unwrap_or("Error");
 
-#[derive(sqlx::FromRow)]
+    page(
+        title,
+        html! {
+            nav class="lists" {
+                a href=(list_url(None)) { "← Back to your lists" }
+            }
+            h1 { (status.as_u16()) " " (title) }
+            p class="search-summary" { (message) }
+        },
+    )
+}
+
+/// Answers requests for pages that don't exist.
+async fn not_found() -> Result<HttpResponse, Error> {
+    Err(Error::NotFound)
+}
+
+#[derive(sqlx::FromRow, Serialize)]
 struct Todo {
     name: String,
     id: u32,
     done: bool,
+    list_id: u32,
+    due_date: Option<NaiveDate>,
+    due_time: Option<NaiveTime>,
+    position: i64,
+    parent_id: Option<u32>,
+    priority: Priority,
+    /// An RRULE value; see `recurrence::Rule`.
+    rrule: Option<String>,
+    /// Not a column; filled in by `tags::load_tags`.
+    #[sqlx(skip)]
+    tags: Vec<String>,
+}
+
+impl Todo {
+    /// Todos without a time are overdue from the day after they're due.
+    fn is_overdue(&self, now: NaiveDateTime) -> bool {
+        match (self.done, self.due_date, self.due_time) {
+            (false, Some(date), Some(time)) => date.and_time(time) < now,
+            (false, Some(date), None) => date < now.date(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(
+    sqlx::Type, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[repr(i32)]
+#[serde(rename_all = "lowercase")]
+enum Priority {
+    #[default]
+    None = 0,
+    Low = 1,
+    Medium = 2,
+    High = 3,
+    Urgent = 4,
+}
+
+impl Priority {
+    const ALL: [Priority; 5] = [
+        Priority::None,
+        Priority::Low,
+        Priority::Medium,
+        Priority::High,
+        Priority::Urgent,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Priority::None => "No priority",
+            Priority::Low => "Low",
+            Priority::Medium => "Medium",
+            Priority::High => "High",
+            Priority::Urgent => "Urgent",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+struct List {
+    id: u32,
+    name: String,
+    sort: Sort,
+}
+
+/// How a list's todos are ordered on the list page. Stored per list.
+#[derive(sqlx::Type, Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+enum Sort {
+    #[default]
+    Manual,
+    Priority,
+    Due,
+    Created,
+}
+
+impl Sort {
+    const ALL: [Sort; 4] = [Sort::Manual, Sort::Priority, Sort::Due, Sort::Created];
+
+    fn label(self) -> &'static str {
+        match self {
+            Sort::Manual => "Manual",
+            Sort::Priority => "Priority",
+            Sort::Due => "Due date",
+            Sort::Created => "Newest",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Sort::Manual => "manual",
+            Sort::Priority => "priority",
+            Sort::Due => "due",
+            Sort::Created => "created",
+        }
+    }
+
+    /// The expressions todos sorted this way are ordered by, all ascending
+    /// and never NULL, so a row value of them can be compared for keyset
+    /// pagination. Each ends in the id to break ties.
+    fn keys(self) -> &'static [&'static str] {
+        match self {
+            Sort::Manual => &["position", "id"],
+            Sort::Priority => &[
+                "-priority",
+                "due_date IS NULL",
+                "COALESCE(due_date, '')",
+                "COALESCE(due_time, '')",
+                "position",
+                "id",
+            ],
+            Sort::Due => &[
+                "due_date IS NULL",
+                "COALESCE(due_date, '')",
+                "COALESCE(due_time, '')",
+                "position",
+                "id",
+            ],
+            // Ids are handed out in creation order.
+            Sort::Created => &["-id"],
+        }
+    }
 }
 
 impl From<sqlx::Error> for Error {
     fn from(value: sqlx::Error) -> Self {
-        Error::Sqlx(value)
+        match value {
+            // A `fetch_one` that found nothing.
+            sqlx::Error::RowNotFound => Error::NotFound,
+            value => Error::Sqlx(value),
+        }
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for Error {
+    fn from(value: sqlx::migrate::MigrateError) -> Self {
+        Error::Migrate(value)
+    }
+}
+
+/// Deserializes an empty form field as `None` and anything else with `FromStr`.
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match Option::<String>::deserialize(deserializer)?
+        .as_deref()
+        .map(str::trim)
+    {
+        None | Some("") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum View {
+    #[default]
+    All,
+    Today,
+    Upcoming,
+    Overdue,
+}
+
+impl View {
+    const ALL: [View; 4] = [View::All, View::Today, View::Upcoming, View::Overdue];
+
+    fn label(self) -> &'static str {
+        match self {
+            View::All => "All",
+            View::Today => "Today",
+            View::Upcoming => "Upcoming",
+            View::Overdue => "Overdue",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            View::All => "all",
+            View::Today => "today",
+            View::Upcoming => "upcoming",
+            View::Overdue => "overdue",
+        }
+    }
+}
+
+/// Which todos the list page shows, by whether they're done.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    #[default]
+    All,
+    Active,
+    Completed,
+}
+
+impl Status {
+    const ALL: [Status; 3] = [Status::All, Status::Active, Status::Completed];
+
+    fn label(self) -> &'static str {
+        match self {
+            Status::All => "All",
+            Status::Active => "Active",
+            Status::Completed => "Completed",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Status::All => "all",
+            Status::Active => "active",
+            Status::Completed => "completed",
+        }
+    }
+}
+
+/// Where a page of the list starts: just after or just before the
+/// top-level todo with this id. Written as `after:12` or `before:12`.
+#[derive(Clone, Copy, PartialEq)]
+enum Cursor {
+    After(u32),
+    Before(u32),
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_id = |id: &str| id.parse().map_err(|_| format!("invalid page “{}”", s));
+        match s.split_once(':') {
+            Some(("after", id)) => parse_id(id).map(Cursor::After),
+            Some(("before", id)) => parse_id(id).map(Cursor::Before),
+            _ => Err(format!("invalid page “{}”", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cursor::After(id) => write!(f, "after:{}", id),
+            Cursor::Before(id) => write!(f, "before:{}", id),
+        }
+    }
+}
+
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 200;
+
+/// Query parameters accepted by the list page.
+#[derive(Deserialize, Default, Clone)]
+struct ListQuery {
+    #[serde(default)]
+    view: View,
+    tag: Option<String>,
+    #[serde(default)]
+    status: Status,
+    /// A cursor from the "Previous" and "Next" links; the first page has none.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    page: Option<Cursor>,
+    per_page: Option<u32>,
+}
+
+impl ListQuery {
+    /// How many top-level todos to show on a page.
+    fn per_page(&self) -> u32 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    /// The URL of list `list_id` with these parameters, leaving out defaults.
+    fn url(&self, list_id: u32) -> String {
+        let mut params = Vec::new();
+        if self.view != View::All {
+            params.push(format!("view={}", self.view.name()));
+        }
+        if let Some(tag) = &self.tag {
+            params.push(format!("tag={}", urlencode(tag)));
+        }
+        if self.status != Status::All {
+            params.push(format!("status={}", self.status.name()));
+        }
+        if let Some(page) = self.page {
+            params.push(format!("page={}", urlencode(&page.to_string())));
+        }
+        if let Some(per_page) = self.per_page {
+            params.push(format!("per_page={}", per_page));
+        }
+
+        if params.is_empty() {
+            format!("/lists/{}", list_id)
+        } else {
+            format!("/lists/{}?{}", list_id, params.join("&"))
+        }
     }
 }
 
 #[get("/")]
-async fn index(state: web::Data<Arc<State>>) -> Result<Markup, Error> {
-    let todos = sqlx::query_as::<_, Todo>(
-        "
-            SELECT id, name, done FROM todos ORDER BY done DESC
-        ",
-    )
-    .fetch_all(&state.db)
-    .await?;
+async fn index(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    csrf: CsrfToken,
+    web::Query(query): web::Query<ListQuery>,
+) -> Result<Markup, Error> {
+    render_list(&state, &user, None, &query, flash::take(&session), &csrf).await
+}
 
-    Ok(html! {
+/// Wraps `content` in the HTML document shared by every page.
+fn page(title: &str, content: Markup) -> Markup {
+    html! {
         (DOCTYPE)
         html {
             head {
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
+                title { (title) }
                 style {
                     "
                         * { margin: 0; padding: 0; border: 0px solid currentColor; box-sizing: border-box; font-family: sans-serif;}
@@ -55,133 +491,804 @@ async fn index(state: web::Data<Arc<State>>) -> Result<Markup, Error> {
                         li button.delete:hover {opacity: 0.5;}
                         li button.delete:active, li button.delete:focus {border: 1px solid currentColor; outline: 0px transparent; }
                         li.completed .text { text-decoration: line-through; }
+                        li:has(> ul.subtasks) { flex-wrap: wrap; }
+                        ul.subtasks { flex-basis: 100%; padding-left: 1.5rem; }
+                        li .progress, li .recurrence { font-size: 0.75rem; color: gray; white-space: nowrap; }
+                        .new-todo-form input.rrule { width: 6rem; padding: 0.25rem 0.5rem; border: 1px solid lightgray; border-radius: 0.25rem; }
+                        .priority { font-size: 0.75rem; padding: 0.125rem 0.5rem; border-radius: 0.25rem; white-space: nowrap; }
+                        .priority.low { background-color: honeydew; }
+                        .priority.medium { background-color: lightyellow; }
+                        .priority.high { background-color: moccasin; }
+                        .priority.urgent { background-color: darkred; color: white; font-weight: bold; }
+                        select { padding: 0.25rem; border: 1px solid lightgray; border-radius: 0.25rem; background: transparent; }
+                        form.sort { display: flex; align-items: center; gap: 0.25rem; margin-bottom: 0.5rem; font-size: 0.875rem; color: gray; }
+                        form.sort button { padding: 0.125rem 0.5rem; border-radius: 0.25rem; background: transparent; color: inherit; }
+                        form.sort button.current { background-color: lightgray; color: black; }
+                        .banner { display: flex; align-items: center; gap: 0.5rem; padding: 0.5rem 1rem; margin-bottom: 0.5rem; border-radius: 0.5rem; background-color: lightyellow; }
+                        .banner button { text-decoration: underline; background: transparent; }
+                        li[draggable] { cursor: grab; }
+                        li.dragging { opacity: 0.5; }
+                        .reorder { display: flex; flex-direction: column; }
+                        .reorder button { background: transparent; font-size: 0.625rem; line-height: 1; opacity: 0.5; }
+                        .reorder button:disabled { visibility: hidden; }
                         .new-todo { padding: 1rem; background-color: lightgray; display: block; border-radius: 0.5rem; flex-grow: 1; }
-                        .new-todo-form { flex-grow: 1; display: flex; }
+                        .new-todo-form { flex-grow: 1; display: flex; gap: 0.5rem; }
+                        .new-todo-form input[type=date], .new-todo-form input[type=time] { padding: 0.5rem; border-radius: 0.5rem; background-color: lightgray; }
+                        li .due { margin-left: auto; font-size: 0.875rem; color: gray; white-space: nowrap; }
+                        li.overdue .due { color: darkred; font-weight: bold; }
+                        li.overdue .text { color: darkred; }
+                        nav.views { display: flex; gap: 0.5rem; margin-bottom: 0.5rem; }
+                        nav.views a { color: inherit; padding: 0.25rem 0.5rem; border-radius: 0.25rem; text-decoration: none; }
+                        nav.views a.current { background-color: lightgray; }
+                        footer.list-footer { display: flex; flex-wrap: wrap; align-items: center; justify-content: space-between; gap: 0.5rem; margin-top: 0.5rem; font-size: 0.875rem; color: gray; }
+                        footer.list-footer nav.views { margin-bottom: 0; }
+                        nav.pages { display: flex; gap: 0.5rem; }
+                        .bulk-actions { display: flex; flex-wrap: wrap; align-items: center; gap: 0.5rem; margin-top: 0.5rem; font-size: 0.875rem; color: gray; }
+                        .bulk-actions form { display: flex; align-items: center; gap: 0.25rem; }
+                        .bulk-actions button { padding: 0.125rem 0.5rem; border-radius: 0.25rem; background-color: lightgray; color: black; }
+                        .bulk-actions form#bulk { margin-right: auto; }
+                        nav.pages a { color: inherit; }
+                        .tag { font-size: 0.75rem; padding: 0.125rem 0.5rem; border-radius: 1rem; background-color: lightblue; color: inherit; text-decoration: none; white-space: nowrap; }
+                        .inline-edit summary { cursor: pointer; list-style: none; opacity: 0.5; }
+                        .inline-edit[open] { display: flex; flex-wrap: wrap; align-items: center; gap: 0.25rem; }
+                        .inline-edit .error { margin-top: 0; flex-basis: 100%; }
+                        .inline-edit input, .new-todo-form input.tags { padding: 0.25rem 0.5rem; border: 1px solid lightgray; border-radius: 0.25rem; }
+                        nav.lists { display: flex; flex-wrap: wrap; align-items: center; gap: 0.5rem; margin-bottom: 1rem; }
+                        nav.lists a { color: inherit; padding: 0.25rem 0.5rem; border-radius: 0.25rem; text-decoration: none; }
+                        nav.lists a.current { background-color: lightgray; font-weight: bold; }
+                        nav.lists input, .list-header input { padding: 0.25rem 0.5rem; border: 1px solid lightgray; border-radius: 0.25rem; }
+                        nav.lists .logout { margin-left: auto; }
+                        .list-header { display: flex; align-items: center; gap: 0.5rem; margin-bottom: 0.5rem; }
+                        .list-header h1 { flex-grow: 1; }
+                        .credentials { display: flex; flex-direction: column; gap: 0.5rem; margin: 1rem 0; }
+                        .credentials input { padding: 0.5rem; border: 1px solid lightgray; border-radius: 0.25rem; }
+                        .error { color: darkred; margin-top: 0.5rem; }
+                        .search { display: flex; margin-bottom: 1rem; }
+                        .search input { flex-grow: 1; padding: 0.5rem; border: 1px solid lightgray; border-radius: 0.25rem; }
+                        .search-summary { color: gray; }
+                        .history-link { text-decoration: none; opacity: 0.5; }
+                        .history time { font-size: 0.875rem; color: gray; white-space: nowrap; }
+                        .history .actor { font-weight: bold; }
+                        .search-results .list-link, .trash .list-link { margin-left: auto; font-size: 0.875rem; color: gray; }
+                        mark { background-color: yellow; }
                     "
                 }
             }
             body {
-                h1 { "Todos" }
-                ul {
-                    @for todo in todos {
-                        li class=(if todo.done {"completed"} else {""}){
-                            form action=(format!("/todos/{}/delete", todo.id)) method="POST" {
-                                button class="delete" aria-label="Delete todo" {
-                                    "❌"
-                                }
-                            }
-                            form action=(format!("/todos/{}/toggle-completion", todo.id)) method="POST" {
-                                button class="text" {
-                                    (todo.name)
-                                }
-                            }
+                (content)
+            }
+        }
+    }
+}
+
+/// Renders the page for `user`'s list `list_id`, or for their first list
+/// when `None` (or when `list_id` doesn't exist).
+async fn render_list(
+    state: &State,
+    user: &CurrentUser,
+    list_id: Option<u32>,
+    query: &ListQuery,
+    flash: Option<Flash>,
+    csrf: &CsrfToken,
+) -> Result<Markup, Error> {
+    if let Some(list_id) = list_id {
+        if !user.owns_list(&state.db, list_id).await? {
+            return Err(Error::NotFound);
+        }
+    }
+
+    let lists = sqlx::query_as::<_, List>(
+        "
+            SELECT id, name, sort FROM lists WHERE user_id=? ORDER BY id
+        ",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db)
+    .await?;
+
+    let current = list_id
+        .and_then(|list_id| lists.iter().find(|list| list.id == list_id))
+        .or(lists.first());
+
+    let items = match current {
+        Some(list) => Some(items::ListItems::load(&state.db, list, query).await?),
+        None => None,
+    };
+
+    let title = current.map_or("Todos", |list| list.name.as_str());
+    let name = items::flash_todo_name(&state.db, flash.as_ref()).await?;
+
+    Ok(page(
+        title,
+        html! {
+            nav class="lists" {
+                @for list in &lists {
+                    a href=(format!("/lists/{}", list.id)) class=(if Some(list.id) == current.map(|current| current.id) {"current"} else {""}) {
+                        (list.name)
+                    }
+                }
+                form action="/lists/new" method="POST" {
+                    (csrf)
+                    input placeholder="New list..." name="name";
+                }
+                a class="logout" href="/import" { "⇅ Import/export" }
+                a href="/trash" { "🗑 Trash" }
+                form action="/logout" method="POST" {
+                    (csrf)
+                    button { "Log out" }
+                }
+            }
+            (search::search_form(""))
+            @if let Some(list) = current {
+                div class="list-header" {
+                    h1 { (list.name) }
+                    form action=(format!("/lists/{}/rename", list.id)) method="POST" {
+                        (csrf)
+                        input name="name" value=(list.name) aria-label="List name";
+                        button { "Rename" }
+                    }
+                    a href=(format!("/lists/{}/calendar", list.id)) aria-label="Calendar feed" { "📅" }
+                    form action=(format!("/lists/{}/delete", list.id)) method="POST" {
+                        (csrf)
+                        button aria-label="Delete list" { "Delete list" }
+                    }
+                }
+                nav class="views" {
+                    @for view in View::ALL {
+                        @let href = ListQuery { view, tag: None, page: None, ..query.clone() }.url(list.id);
+                        a href=(href) class=(if view == query.view {"current"} else {""}) {
+                            (view.label())
+                        }
+                    }
+                    @if let Some(tag) = &query.tag {
+                        @let href = ListQuery { tag: None, page: None, ..query.clone() }.url(list.id);
+                        a class="tag current" href=(href) aria-label="Clear tag filter" {
+                            (tag) " ✕"
+                        }
+                    }
+                }
+                form class="sort" action=(format!("/lists/{}/sort", list.id)) method="POST" {
+                    (csrf)
+                    "Sort by"
+                    @for mode in Sort::ALL {
+                        button.current[mode == list.sort] name="sort" value=(mode.name()) { (mode.label()) }
+                    }
+                }
+                (items::notices(flash.as_ref(), name.as_deref(), csrf))
+                ul class="todos" data-list-id=(list.id) data-offset=(items.as_ref().map_or(0, |items| items.offset)) {
+                    @if let Some(items) = &items {
+                        @let (roots, ctx) = items.tree(flash.as_ref(), csrf);
+                        @for (i, todo) in roots.iter().enumerate() {
+                            (items::todo_item(todo, items.offset + i, items.total_roots, &ctx))
                         }
                     }
                     li {
                         form class="new-todo-form" action="/todos/new" method="POST" {
+                            (csrf)
+                            input type="hidden" name="list_id" value=(list.id);
                             input class="new-todo" placeholder="Create new TODO..." name="name" autofocus;
+                            input class="tags" name="tags" placeholder="Tags" aria-label="Tags";
+                            (priority_select(Priority::None))
+                            input class="rrule" name="rrule" placeholder="Repeat" aria-label="Repeat rule" list="rrules";
+                            input type="date" name="due_date" aria-label="Due date";
+                            input type="time" name="due_time" aria-label="Due time";
                         }
                     }
                 }
+                @if let Some(items) = &items {
+                    (items::footer(items, query))
+                }
+                (bulk::bulk_actions(list, &lists, csrf))
+                datalist id="rrules" {
+                    @for (rrule, label) in RRULE_EXAMPLES {
+                        option value=(rrule) { (label) }
+                    }
+                }
+                @if items.as_ref().is_some_and(|items| items.reorderable) {
+                    script { (PreEscaped(REORDER_SCRIPT)) }
+                }
+                script { (PreEscaped(FRAGMENT_SCRIPT)) }
+                script { (PreEscaped(LIVE_SCRIPT)) }
+            } @else {
+                h1 { "Todos" }
+                p { "Create a list to get started." }
+            }
+        },
+    ))
+}
+
+/// Suggestions for the repeat rule inputs.
+const RRULE_EXAMPLES: [(&str, &str); 5] = [
+    ("FREQ=DAILY", "Every day"),
+    ("FREQ=WEEKLY", "Every week"),
+    ("FREQ=WEEKLY;BYDAY=MO,WE", "Every Monday and Wednesday"),
+    ("FREQ=MONTHLY;BYMONTHDAY=15", "Every month on the 15th"),
+    ("FREQ=YEARLY", "Every year"),
+];
+
+fn priority_select(selected: Priority) -> Markup {
+    html! {
+        select name="priority" aria-label="Priority" {
+            @for priority in Priority::ALL {
+                option value=(priority.name()) selected[priority == selected] { (priority.label()) }
+            }
+        }
+    }
+}
+
+/// Drag-and-drop reordering for the list page. The ▲/▼ buttons do the same
+/// thing without JavaScript.
+const REORDER_SCRIPT: &str = r#"
+    const list = document.querySelector("ul.todos");
+    let dragged = null;
+
+    list.addEventListener("dragstart", (event) => {
+        // Nested items are draggable too; only move the innermost one.
+        event.stopPropagation();
+        dragged = event.target.closest("li[data-id]");
+        dragged.classList.add("dragging");
+    });
+
+    list.addEventListener("dragend", () => {
+        dragged?.classList.remove("dragging");
+    });
+
+    list.addEventListener("dragover", (event) => {
+        // Todos can only be reordered among their siblings.
+        let target = event.target.closest("li[data-id]");
+        while (target && dragged && target.parentNode !== dragged.parentNode) {
+            target = target.parentNode.closest("li[data-id]");
+        }
+        if (!dragged || !target) return;
+
+        event.preventDefault();
+        if (target === dragged || target.contains(dragged)) return;
+
+        const rect = target.getBoundingClientRect();
+        const after = event.clientY > rect.top + rect.height / 2;
+        target.parentNode.insertBefore(dragged, after ? target.nextSibling : target);
+    });
+
+    list.addEventListener("drop", (event) => {
+        event.preventDefault();
+        if (!dragged) return;
+
+        const siblings = [...dragged.parentNode.children].filter((li) => li.dataset.id);
+        // Top-level todos are paged, so count the ones on earlier pages too.
+        const offset = dragged.parentNode === list ? Number(list.dataset.offset) : 0;
+        const position = offset + siblings.indexOf(dragged);
+        const csrf_token = dragged.querySelector("input[name=csrf_token]").value;
+        fetch(`/todos/${dragged.dataset.id}/move`, {
+            method: "POST",
+            body: new URLSearchParams({ position, csrf_token }),
+        }).finally(() => location.reload());
+    });
+"#;
+
+/// Submits the forms on a todo, and the new-todo form, in the background and
+/// swaps the changed todos and notices the server sends back into the page.
+/// Without JavaScript the forms post and redirect as usual.
+const FRAGMENT_SCRIPT: &str = r#"
+    (() => {
+        const list = document.querySelector("ul.todos");
+
+        list.addEventListener("submit", async (event) => {
+            const form = event.target;
+            // Moving a todo renumbers its siblings, so that reloads the page.
+            if (form.closest(".reorder")) return;
+            event.preventDefault();
+
+            const target = form.closest("ul.todos > li[data-id]");
+            const response = await fetch(form.action, {
+                method: "POST",
+                body: new URLSearchParams(new FormData(form)),
+                headers: { "HX-Request": "true", "HX-Current-URL": location.href },
+            });
+            if (!response.ok || response.redirected) return location.reload();
+
+            const fragment = document.createElement("template");
+            fragment.innerHTML = await response.text();
+            document.querySelector(".notices").replaceWith(fragment.content.querySelector(".notices"));
+            const footer = fragment.content.querySelector(".list-footer");
+            if (footer) document.querySelector(".list-footer")?.replaceWith(footer);
+
+            const items = fragment.content.querySelectorAll(":scope > li");
+            if (target) {
+                target.replaceWith(...items);
+            } else {
+                const newTodo = [...list.children].find((li) => !li.dataset.id);
+                items.forEach((li) => list.insertBefore(li, newTodo));
+                form.reset();
             }
+        });
+    })();
+"#;
+
+/// Keeps the page up to date with changes made elsewhere: when one of this
+/// list's todos changes, the page is fetched again and its todos and footer
+/// swapped in.
+/// The new-todo form stays put, and a todo being edited or dragged holds off
+/// the swap until it's done.
+const LIVE_SCRIPT: &str = r#"
+    (() => {
+        const list = document.querySelector("ul.todos");
+        const listId = Number(list.dataset.listId);
+        let timer = null;
+
+        const refresh = (delay = 200) => {
+            clearTimeout(timer);
+            timer = setTimeout(async () => {
+                const items = [...list.children].filter((li) => li.dataset.id);
+                const busy = items.some((li) => li.contains(document.activeElement))
+                    || list.querySelector("details[open], .dragging");
+                if (busy) return refresh(2000);
+
+                const response = await fetch(location.href);
+                const page = new DOMParser().parseFromString(await response.text(), "text/html");
+                const fresh = page.querySelector("ul.todos");
+                if (!response.ok || !fresh) return;
+
+                items.forEach((li) => li.remove());
+                const newTodo = [...list.children].find((li) => !li.dataset.id);
+                for (const li of [...fresh.children].filter((li) => li.dataset.id)) {
+                    list.insertBefore(li, newTodo);
+                }
+                list.dataset.offset = fresh.dataset.offset;
+                const footer = page.querySelector(".list-footer");
+                if (footer) document.querySelector(".list-footer")?.replaceWith(footer);
+            }, delay);
+        };
+
+        const events = new EventSource("/events");
+        for (const change of ["created", "updated", "deleted"]) {
+            events.addEventListener(change, (event) => {
+                if (JSON.parse(event.data).list_id === listId) refresh();
+            });
         }
-    })
+        events.addEventListener("resync", () => refresh());
+
+        // Catch up on whatever happened while the connection was down.
+        let connected = false;
+        events.addEventListener("open", () => {
+            if (connected) refresh();
+            connected = true;
+        });
+    })();
+"#;
+
+/// Percent-encodes `value` for use in a query string.
+fn urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Where to send the browser after changing a todo in `list_id`.
+fn list_url(list_id: Option<u32>) -> String {
+    match list_id {
+        Some(list_id) => format!("/lists/{}", list_id),
+        None => "/".to_string(),
+    }
 }
 
 #[derive(Deserialize)]
 struct NewTodoForm {
     name: String,
+    list_id: u32,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    due_date: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    due_time: Option<NaiveTime>,
+    #[serde(default)]
+    tags: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    parent_id: Option<u32>,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    rrule: String,
 }
 
 #[post("/todos/new")]
 async fn new_todo(
     state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
     web::Form(form): web::Form<NewTodoForm>,
 ) -> Result<impl Responder, Error> {
-    sqlx::query(
+    // Subtasks always live in their parent's list.
+    let list_id = match form.parent_id {
+        Some(parent_id) if user.owns_todo(&state.db, parent_id).await? => {
+            sqlx::query_scalar::<_, u32>(
+                "
+                    SELECT list_id FROM todos WHERE id=?
+                ",
+            )
+            .bind(parent_id)
+            .fetch_one(&state.db)
+            .await?
+        }
+        Some(_) => return Err(Error::NotFound),
+        None if user.owns_list(&state.db, form.list_id).await? => form.list_id,
+        None => return Err(Error::NotFound),
+    };
+
+    let name = form.name.trim();
+    let rrule = recurrence::parse_rrule(&form.rrule);
+    let error = match &rrule {
+        _ if name.is_empty() => Some("A todo needs a name".to_string()),
+        Err(message) => Some(format!("Invalid repeat rule: {}", message)),
+        Ok(_) => None,
+    };
+
+    if let Some(message) = error {
+        let flash = Flash::NewTodoError { message };
+        return items::reply(&state, &request, &session, list_id, &[], Some(flash)).await;
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let id = sqlx::query_scalar::<_, u32>(
         "
-            INSERT INTO todos (name) VALUES (?)
+            INSERT INTO todos (name, list_id, due_date, due_time, parent_id, priority, rrule, position)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, (
+                SELECT COALESCE(MAX(position) + 1, 0) FROM todos
+                WHERE list_id = ?2 AND parent_id IS ?5
+            ))
+            RETURNING id
         ",
     )
-    .bind(form.name)
-    .execute(&state.db)
+    .bind(name)
+    .bind(list_id)
+    .bind(form.due_date)
+    // A time without a date isn't a deadline.
+    .bind(form.due_time.filter(|_| form.due_date.is_some()))
+    .bind(form.parent_id)
+    .bind(form.priority)
+    .bind(rrule.ok().flatten())
+    .fetch_one(&mut *tx)
     .await?;
 
-    Ok(web::Redirect::to("/").see_other())
+    tags::set_tags(&mut tx, user.id, id, &tags::parse_tags(&form.tags)).await?;
+    history::record(&mut tx, id, Some(user.id), history::Action::Created).await?;
+
+    tx.commit().await?;
+
+    state.live.publish(user.id, Change::Created, id, list_id);
+
+    items::reply(&state, &request, &session, list_id, &[id], None).await
 }
 
-#[post("/todos/{id}/toggle-completion")]
-async fn update_todo_completion(
+#[derive(Deserialize)]
+struct EditTodoForm {
+    name: String,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    rrule: String,
+}
+
+#[post("/todos/{id}/edit")]
+async fn edit_todo(
     state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
     id: web::Path<u32>,
+    web::Form(form): web::Form<EditTodoForm>,
 ) -> Result<impl Responder, Error> {
-    sqlx::query(
+    let id = id.into_inner();
+
+    if !user.owns_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let rrule = recurrence::parse_rrule(&form.rrule);
+    let error = match &rrule {
+        _ if form.name.trim().is_empty() => Some("A todo needs a name".to_string()),
+        Err(message) => Some(format!("Invalid repeat rule: {}", message)),
+        Ok(_) => None,
+    };
+
+    if let Some(message) = error {
+        let list_id = sqlx::query_scalar::<_, u32>(
+            "
+                SELECT list_id FROM todos WHERE id=?
+            ",
+        )
+        .bind(id)
+        .fetch_one(&state.db)
+        .await?;
+
+        let flash = Flash::EditError {
+            todo_id: id,
+            message,
+        };
+        return items::reply(&state, &request, &session, list_id, &[id], Some(flash)).await;
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let before = history::snapshot(&mut tx, id).await?;
+
+    let updated = sqlx::query(
         "
-            UPDATE todos SET done = NOT done WHERE id=?
+            UPDATE todos SET name=?, priority=?, rrule=? WHERE id=? AND deleted_at IS NULL
         ",
     )
-    .bind(id.into_inner())
-    .execute(&state.db)
+    .bind(form.name.trim())
+    .bind(form.priority)
+    .bind(rrule.ok().flatten())
+    .bind(id)
+    .execute(&mut *tx)
     .await?;
 
-    Ok(web::Redirect::to("/").see_other())
+    // Gone since the check above, e.g. deleted in another tab.
+    if updated.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    let after = history::snapshot(&mut tx, id).await?;
+    if let (Some(before), Some(after)) = (&before, &after) {
+        history::record_changes(&mut tx, user.id, before, after).await?;
+    }
+
+    tx.commit().await?;
+
+    let Some(after) = after else {
+        return Err(Error::NotFound);
+    };
+
+    state
+        .live
+        .publish(user.id, Change::Updated, id, after.list_id);
+
+    items::reply(&state, &request, &session, after.list_id, &[id], None).await
 }
 
-#[post("/todos/{id}/delete")]
-async fn delete_todo(
+#[post("/todos/{id}/toggle-completion")]
+async fn update_todo_completion(
     state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
     id: web::Path<u32>,
 ) -> Result<impl Responder, Error> {
-    sqlx::query(
+    let id = id.into_inner();
+
+    if !user.owns_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let Some((list_id, done)) = sqlx::query_as::<_, (u32, bool)>(
         "
-            DELETE FROM todos WHERE id=?
+            UPDATE todos SET done = NOT done WHERE id=? AND deleted_at IS NULL
+            RETURNING list_id, done
         ",
     )
-    .bind(id.into_inner())
-    .execute(&state.db)
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(Error::NotFound);
+    };
+
+    history::record_change(
+        &mut tx,
+        id,
+        user.id,
+        "done",
+        Some((!done).to_string()),
+        Some(done.to_string()),
+    )
     .await?;
 
-    Ok(web::Redirect::to("/").see_other())
+    let next = if done {
+        let today = Local::now().date_naive();
+        recurrence::create_next_occurrence(&mut tx, id, user.id, today).await?
+    } else {
+        None
+    };
+
+    tx.commit().await?;
+
+    state.live.publish(user.id, Change::Updated, id, list_id);
+    if let Some(next) = next {
+        state.live.publish(user.id, Change::Created, next, list_id);
+    }
+
+    // Offer to finish off the subtasks too, rather than doing it unasked.
+    let open = if done {
+        subtasks::count_open_subtasks(&state.db, id).await?
+    } else {
+        0
+    };
+    let flash = (open > 0).then_some(Flash::CompleteSubtasks { todo_id: id, open });
+
+    let changed: Vec<u32> = std::iter::once(id).chain(next).collect();
+    items::reply(&state, &request, &session, list_id, &changed, flash).await
 }
 
-async fn initialize_db() -> Result<SqlitePool, sqlx::Error> {
-    let db = SqlitePool::connect("sqlite::memory:").await?;
+/// Moves a todo and its subtasks to the trash, offering to undo it.
+#[post("/todos/{id}/delete")]
+async fn delete_todo(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    if !trash::trash_todo(&mut tx, id, user.id).await? {
+        return Err(Error::NotFound);
+    }
 
-    sqlx::query(
+    let (list_id, parent_id) = sqlx::query_as::<_, (u32, Option<u32>)>(
         "
-            CREATE TABLE todos (
-                id INTEGER NOT NULL PRIMARY KEY,
-                name STRING NOT NULL,
-                done BOOL NOT NULL DEFAULT false
-            )
+            SELECT list_id, parent_id FROM todos WHERE id=?
         ",
     )
-    .execute(&db)
+    .bind(id)
+    .fetch_one(&mut *tx)
     .await?;
 
+    tx.commit().await?;
+
+    state.live.publish(user.id, Change::Deleted, id, list_id);
+
+    // A deleted subtask leaves its parent behind, which is what changed.
+    let changed: Vec<u32> = parent_id.into_iter().collect();
+    let flash = Flash::Deleted { todo_id: id };
+    items::reply(&state, &request, &session, list_id, &changed, Some(flash)).await
+}
+
+async fn initialize_db(database_url: &str) -> Result<SqlitePool, Error> {
+    let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+    let db = SqlitePool::connect_with(options).await?;
+
+    sqlx::migrate!().run(&db).await?;
+
     Ok(db)
 }
 
 #[actix_web::main]
-async fn main() {
-    env_logger::init();
+async fn main() -> ExitCode {
+    let config = match Config::load(std::env::args()) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::PrintConfig(config)) => {
+            print!("{}", config.to_toml());
+            return ExitCode::SUCCESS;
+        }
+        // Help goes to stdout with success, mistakes to stderr with usage.
+        Err(ConfigError::Args(err)) => {
+            let _ = err.print();
+            return ExitCode::from(err.exit_code() as u8);
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::from(2);
+        }
+    };
 
-    let state = Arc::new(State {
-        db: initialize_db().await.expect("Failed to initialize db"),
-    });
+    env_logger::Builder::new()
+        .parse_filters(&config.log_level)
+        .init();
+
+    match run(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The whole app, wired up around `state`; `run` serves it, and the tests
+/// drive it with `actix_web::test`.
+fn app(
+    state: Arc<State>,
+    session_key: Key,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .wrap(csrf::Csrf)
+        .wrap(
+            // The app is served over plain HTTP, so the cookie can't be `Secure`.
+            SessionMiddleware::builder(CookieSessionStore::default(), session_key)
+                .cookie_secure(false)
+                .build(),
+        )
+        .wrap(Logger::default())
+        .wrap(metrics::RecordMetrics(state.clone()))
+        .app_data(web::Data::new(state))
+        .app_data(
+            web::FormConfig::default()
+                .error_handler(|err, _req| Error::Validation(err.to_string()).into()),
+        )
+        .app_data(
+            web::QueryConfig::default()
+                .error_handler(|err, _req| Error::Validation(err.to_string()).into()),
+        )
+        .app_data(web::PathConfig::default().error_handler(|_err, _req| Error::NotFound.into()))
+        .service(index)
+        .service(new_todo)
+        .service(edit_todo)
+        .service(update_todo_completion)
+        .service(delete_todo)
+        .configure(auth::config)
+        .configure(bulk::config)
+        .configure(health::config)
+        .configure(history::config)
+        .configure(ical::config)
+        .configure(lists::config)
+        .configure(live::config)
+        .configure(metrics::config)
+        .configure(reorder::config)
+        .configure(search::config)
+        .configure(subtasks::config)
+        .configure(tags::config)
+        .configure(transfer::config)
+        .configure(trash::config)
+        .configure(api::config)
+        .default_service(web::to(not_found))
+}
+
+/// Serves the app until it's shut down.
+async fn run(config: Config) -> Result<(), String> {
+    // Without a fixed key, sessions don't survive a restart.
+    let session_key = match &config.session_key {
+        Some(key) => {
+            Key::try_from(key.as_bytes()).map_err(|err| format!("invalid SESSION_KEY: {}", err))?
+        }
+        None => Key::generate(),
+    };
+
+    let db = initialize_db(&config.database_url).await.map_err(|err| {
+        format!(
+            "couldn't open the database at {}: {}",
+            config.database_url, err
+        )
+    })?;
+    let state = Arc::new(State::new(db));
+
+    trash::spawn_purge_task(state.db.clone(), config.trash_max_age_days);
+
+    let server = HttpServer::new(move || app(state.clone(), session_key.clone()))
+        .bind((config.host.as_str(), config.port))
+        .map_err(|err| {
+            format!(
+                "couldn't listen on {}:{}: {}",
+                config.host, config.port, err
+            )
+        })?;
+
+    log::info!("Listening on http://{}:{}", config.host, config.port);
 
-    HttpServer::new(move || {
-        App::new()
-            .wrap(Logger::default())
-            .app_data(web::Data::new(state.clone()))
-            .service(index)
-            .service(new_todo)
-            .service(update_todo_completion)
-            .service(delete_todo)
-    })
-    .bind(("127.0.0.1", 3000))
-    .expect("Failed to get port")
-    .run()
-    .await
-    .expect("Failed to await server creation")
+    server
+        .run()
+        .await
+        .map_err(|err| format!("server stopped: {}", err))
 }
@@ -0,0 +1,246 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    get,
+    http::{Method, StatusCode},
+    web, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::{Error, State};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The route label of requests that didn't match a route, so scanners can't
+/// add a series per URL.
+const UNMATCHED: &str = "unmatched";
+
+#[derive(Default)]
+struct Histogram {
+    /// How many observations fell into each bucket (not cumulative).
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// By method, route and status.
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// By method and route.
+    durations: BTreeMap<(String, String), Histogram>,
+}
+
+/// Request counts and latencies per route, for `/metrics`.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Metrics::default()
+    }
+
+    fn record(&self, method: &Method, route: String, status: StatusCode, seconds: f64) {
+        // A panic elsewhere while holding the lock leaves the counts usable.
+        let mut registry = self
+            .registry
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *registry
+            .requests
+            .entry((method.to_string(), route.clone(), status.as_u16()))
+            .or_default() += 1;
+        registry
+            .durations
+            .entry((method.to_string(), route))
+            .or_default()
+            .observe(seconds);
+    }
+}
+
+/// Escapes a label value for the Prometheus text format.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders the metrics, plus the todo gauges, in the Prometheus text format.
+fn render(recorded: &Metrics, todos: u32, completed: u32) -> String {
+    let registry = recorded
+        .registry
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut text = String::new();
+
+    // Writing to a String can't fail.
+    let _ = writeln!(
+        text,
+        "# HELP http_requests_total HTTP requests handled, by method, route and status."
+    );
+    let _ = writeln!(text, "# TYPE http_requests_total counter");
+    for ((method, route, status), count) in &registry.requests {
+        let _ = writeln!(
+            text,
+            "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+            method,
+            label(route),
+            status,
+            count
+        );
+    }
+
+    let _ = writeln!(
+        text,
+        "# HELP http_request_duration_seconds How long requests took, by method and route."
+    );
+    let _ = writeln!(text, "# TYPE http_request_duration_seconds histogram");
+    for ((method, route), histogram) in &registry.durations {
+        let labels = format!("method=\"{}\",route=\"{}\"", method, label(route));
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            text,
+            "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            labels, histogram.count
+        );
+        let _ = writeln!(
+            text,
+            "http_request_duration_seconds_sum{{{}}} {}",
+            labels, histogram.sum
+        );
+        let _ = writeln!(
+            text,
+            "http_request_duration_seconds_count{{{}}} {}",
+            labels, histogram.count
+        );
+    }
+
+    let _ = writeln!(text, "# HELP todos Todos outside the trash.");
+    let _ = writeln!(text, "# TYPE todos gauge");
+    let _ = writeln!(text, "todos {}", todos);
+    let _ = writeln!(
+        text,
+        "# HELP todos_completed Completed todos outside the trash."
+    );
+    let _ = writeln!(text, "# TYPE todos_completed gauge");
+    let _ = writeln!(text, "todos_completed {}", completed);
+
+    text
+}
+
+/// Everything the server has counted, in the Prometheus text format. Needs
+/// no session, like a scraper.
+#[get("/metrics")]
+async fn metrics(state: web::Data<Arc<State>>) -> Result<HttpResponse, Error> {
+    let (todos, completed) = sqlx::query_as::<_, (u32, u32)>(
+        "
+            SELECT COUNT(*), COALESCE(SUM(done), 0) FROM todos WHERE deleted_at IS NULL
+        ",
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(render(&state.metrics, todos, completed)))
+}
+
+/// Counts every request and times it, by the route it matched.
+pub(crate) struct RecordMetrics(pub(crate) Arc<State>);
+
+impl<S, B> Transform<S, ServiceRequest> for RecordMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RecordMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordMetricsMiddleware {
+            service: Rc::new(service),
+            state: self.0.clone(),
+        }))
+    }
+}
+
+pub(crate) struct RecordMetricsMiddleware<S> {
+    service: Rc<S>,
+    state: Arc<State>,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let state = self.state.clone();
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let method = req.method().clone();
+
+            let result = service.call(req).await;
+
+            // The route is only known once the request has been routed;
+            // requests turned away before that, like those without a CSRF
+            // token, count as unmatched.
+            let (route, status) = match &result {
+                Ok(res) => (res.request().match_pattern(), res.status()),
+                Err(err) => (None, err.as_response_error().status_code()),
+            };
+            state.metrics.record(
+                &method,
+                route.unwrap_or_else(|| UNMATCHED.to_string()),
+                status,
+                start.elapsed().as_secs_f64(),
+            );
+
+            result
+        })
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(metrics);
+}
@@ -0,0 +1,33 @@
+use actix_multipart::Multipart;
+use futures_util::TryStreamExt;
+use std::collections::HashMap;
+
+/// Room in a `multipart/form-data` body for the boundaries, the part headers
+/// and the small fields that go along with a file.
+pub(crate) const OVERHEAD: usize = 64 * 1024;
+
+/// The fields of a `multipart/form-data` body, by name, or `None` if it
+/// can't be read or they add up to more than `limit` bytes. Every part is
+/// read into memory, which is all the import form needs.
+pub(crate) async fn read_fields(
+    mut multipart: Multipart,
+    limit: usize,
+) -> Option<HashMap<String, Vec<u8>>> {
+    let mut fields = HashMap::new();
+    let mut size = 0;
+
+    while let Some(mut field) = multipart.try_next().await.ok()? {
+        let name = field.name()?.to_string();
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await.ok()? {
+            size += chunk.len();
+            if size > limit {
+                return None;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        fields.insert(name, data);
+    }
+
+    Some(fields)
+}
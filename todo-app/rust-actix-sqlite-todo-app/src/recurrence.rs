@@ -0,0 +1,734 @@
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+use sqlx::SqliteConnection;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{history, Todo};
+
+/// How often a rule repeats; the `FREQ` part of an RRULE.
+#[derive(Clone, Copy, PartialEq)]
+enum Freq {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// The subset of RFC 5545 recurrence rules that todos support: `FREQ`,
+/// `INTERVAL`, `BYDAY` (plain weekdays only), `BYMONTHDAY`, `COUNT` and
+/// `UNTIL`. Occurrences are counted from the todo's due date rather than a
+/// separate `DTSTART`.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Rule {
+    freq: Freq,
+    interval: u32,
+    by_day: Vec<Weekday>,
+    by_month_day: Vec<i32>,
+    count: Option<u32>,
+    until: Option<NaiveDate>,
+}
+
+/// The largest `INTERVAL`, which keeps the date arithmetic well within range.
+const MAX_INTERVAL: u32 = 1000;
+
+/// The longest rule accepted. Errors quote the rule, and end up in the
+/// session cookie.
+const MAX_RRULE_LEN: usize = 200;
+
+/// The Gregorian calendar repeats every 400 years, so a date that doesn't
+/// come up again within that many steps never will.
+const CALENDAR_CYCLE_YEARS: u32 = 400;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon),
+    ("TU", Weekday::Tue),
+    ("WE", Weekday::Wed),
+    ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri),
+    ("SA", Weekday::Sat),
+    ("SU", Weekday::Sun),
+];
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let value = value
+            .strip_prefix("RRULE:")
+            .or_else(|| value.strip_prefix("rrule:"))
+            .unwrap_or(value);
+
+        let mut freq = None;
+        let mut rule = Rule {
+            freq: Freq::Daily,
+            interval: 1,
+            by_day: vec![],
+            by_month_day: vec![],
+            count: None,
+            until: None,
+        };
+
+        for part in value.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("“{}” isn't of the form NAME=VALUE", part))?;
+            let value = value.to_ascii_uppercase();
+
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.as_str() {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        "MONTHLY" => Freq::Monthly,
+                        "YEARLY" => Freq::Yearly,
+                        _ => return Err(format!("unsupported FREQ “{}”", value)),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| (1..=MAX_INTERVAL).contains(interval))
+                        .ok_or_else(|| {
+                            format!("INTERVAL must be a number from 1 to {}", MAX_INTERVAL)
+                        })?
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        let weekday = WEEKDAYS
+                            .iter()
+                            .find(|(name, _)| *name == day)
+                            .map(|(_, weekday)| *weekday)
+                            .ok_or_else(|| format!("unsupported BYDAY “{}”", day))?;
+                        if !rule.by_day.contains(&weekday) {
+                            rule.by_day.push(weekday);
+                        }
+                    }
+                    rule.by_day.sort_by_key(Weekday::num_days_from_monday);
+                }
+                "BYMONTHDAY" => {
+                    for day in value.split(',') {
+                        let day = day
+                            .parse::<i32>()
+                            .ok()
+                            .filter(|day| (1..=31).contains(&day.abs()))
+                            .ok_or_else(|| format!("invalid BYMONTHDAY “{}”", day))?;
+                        if !rule.by_month_day.contains(&day) {
+                            rule.by_month_day.push(day);
+                        }
+                    }
+                }
+                "COUNT" => {
+                    rule.count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|count| *count > 0)
+                            .ok_or("COUNT must be a positive number")?,
+                    )
+                }
+                "UNTIL" => {
+                    rule.until = Some(
+                        value
+                            .get(..8)
+                            .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+                            .ok_or_else(|| format!("invalid UNTIL “{}”", value))?,
+                    )
+                }
+                "WKST" if value == "MO" => {}
+                name => return Err(format!("unsupported rule part {}", name)),
+            }
+        }
+
+        rule.freq = freq.ok_or("a recurrence rule needs a FREQ")?;
+
+        if !rule.by_month_day.is_empty() && rule.freq != Freq::Monthly {
+            return Err("BYMONTHDAY is only supported with FREQ=MONTHLY".to_string());
+        }
+        if !rule.by_day.is_empty() && !matches!(rule.freq, Freq::Daily | Freq::Weekly) {
+            return Err("BYDAY is only supported with FREQ=DAILY or FREQ=WEEKLY".to_string());
+        }
+        if !rule.by_day.is_empty() && rule.freq == Freq::Daily && rule.interval.is_multiple_of(7) {
+            // Every occurrence would fall on the due date's weekday, so the
+            // series would end at once if that isn't one of the days.
+            return Err(
+                "BYDAY with FREQ=DAILY needs an INTERVAL that isn't a multiple of 7; \
+                 use FREQ=WEEKLY"
+                    .to_string(),
+            );
+        }
+        if rule.count.is_some() && rule.until.is_some() {
+            return Err("COUNT and UNTIL can't be used together".to_string());
+        }
+
+        Ok(rule)
+    }
+}
+
+/// Formats the rule as a canonical RRULE value (without the `RRULE:` prefix).
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freq = match self.freq {
+            Freq::Daily => "DAILY",
+            Freq::Weekly => "WEEKLY",
+            Freq::Monthly => "MONTHLY",
+            Freq::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", freq)?;
+
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> = self.by_day.iter().map(|day| weekday_code(*day)).collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(i32::to_string).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    WEEKDAYS
+        .iter()
+        .find(|(_, day)| *day == weekday)
+        .map_or("", |(name, _)| name)
+}
+
+/// The `day`th day of the given month, counting back from the end when
+/// `day` is negative. `None` if the month is too short.
+fn month_day(year: i32, month: u32, day: i32) -> Option<NaiveDate> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    if day > 0 {
+        first.with_day(day as u32)
+    } else {
+        let last = first.checked_add_months(Months::new(1))?.pred_opt()?;
+        last.checked_sub_days(Days::new((-day - 1) as u64))
+            .filter(|date| date.month() == month)
+    }
+}
+
+impl Rule {
+    /// A short human-readable summary, e.g. "Every 2 weeks on Mon, Wed".
+    pub(crate) fn describe(&self) -> String {
+        let unit = match self.freq {
+            Freq::Daily => "day",
+            Freq::Weekly => "week",
+            Freq::Monthly => "month",
+            Freq::Yearly => "year",
+        };
+        let mut description = match self.interval {
+            1 => format!("Every {}", unit),
+            interval => format!("Every {} {}s", interval, unit),
+        };
+
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self.by_day.iter().map(Weekday::to_string).collect();
+            description += &format!(" on {}", days.join(", "));
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self
+                .by_month_day
+                .iter()
+                .map(|day| match day {
+                    -1 => "the last day".to_string(),
+                    day if *day < 0 => format!("the {} last day", ordinal(-day)),
+                    day => format!("the {}", ordinal(*day)),
+                })
+                .collect();
+            description += &format!(" on {}", days.join(", "));
+        }
+        if let Some(count) = self.count {
+            description += &match count - 1 {
+                0 => ", last time".to_string(),
+                1 => ", 1 more time".to_string(),
+                more => format!(", {} more times", more),
+            };
+        }
+        if let Some(until) = self.until {
+            description += &format!(" until {}", until.format("%-d %b %Y"));
+        }
+
+        description
+    }
+
+    /// The first occurrence strictly after `date`, ignoring `COUNT` and `UNTIL`.
+    fn next_date(&self, date: NaiveDate) -> Option<NaiveDate> {
+        match self.freq {
+            Freq::Daily if self.by_day.is_empty() => {
+                date.checked_add_days(Days::new(self.interval.into()))
+            }
+            // With an interval that isn't a multiple of 7, every weekday
+            // comes up within 7 steps.
+            Freq::Daily => (1..=7)
+                .filter_map(|step| {
+                    date.checked_add_days(Days::new(step * u64::from(self.interval)))
+                })
+                .find(|date| self.by_day.contains(&date.weekday())),
+            Freq::Weekly => {
+                let days = match self.by_day.as_slice() {
+                    [] => vec![date.weekday()],
+                    days => days.to_vec(),
+                };
+                let weekday = date.weekday().num_days_from_monday();
+
+                // Later in the same week, or else the first day `interval`
+                // weeks on.
+                match days
+                    .iter()
+                    .map(Weekday::num_days_from_monday)
+                    .find(|day| *day > weekday)
+                {
+                    Some(day) => date.checked_add_days(Days::new((day - weekday).into())),
+                    None => {
+                        let monday = date.checked_sub_days(Days::new(weekday.into()))?;
+                        let week =
+                            monday.checked_add_days(Days::new(7 * u64::from(self.interval)))?;
+                        week.checked_add_days(Days::new(days[0].num_days_from_monday().into()))
+                    }
+                }
+            }
+            Freq::Monthly => {
+                let days = match self.by_month_day.as_slice() {
+                    [] => vec![date.day() as i32],
+                    days => days.to_vec(),
+                };
+                let first = date.with_day(1)?;
+
+                // Months without a matching day (like the 31st in April)
+                // are skipped, as RFC 5545 requires.
+                (0..120u32).find_map(|step| {
+                    let months = step.checked_mul(self.interval)?;
+                    let month = first.checked_add_months(Months::new(months))?;
+                    days.iter()
+                        .filter_map(|day| month_day(month.year(), month.month(), *day))
+                        .filter(|candidate| *candidate > date)
+                        .min()
+                })
+            }
+            // Only a leap day can be missing, and it comes back within a
+            // calendar cycle if it ever does.
+            Freq::Yearly => (1..=CALENDAR_CYCLE_YEARS).find_map(|step| {
+                let years = i32::try_from(step.checked_mul(self.interval)?).ok()?;
+                NaiveDate::from_ymd_opt(date.year().checked_add(years)?, date.month(), date.day())
+            }),
+        }
+    }
+
+    /// Jumps from occurrence `date` over the whole periods of the rule that
+    /// end before `today`, returning where that lands and how many
+    /// occurrences it passed. Only rules with the same occurrences in every
+    /// period jump; for the rest, the dates are stepped through from `date`.
+    fn skip_periods(&self, date: NaiveDate, today: NaiveDate) -> (NaiveDate, u32) {
+        let interval = i64::from(self.interval);
+        let per_period = self.by_day.len().max(self.by_month_day.len()).max(1) as i64;
+        let add_months = |months: i64| {
+            let months = u32::try_from(months).ok()?;
+            date.checked_add_months(Months::new(months))
+        };
+
+        let (periods, end) = match self.freq {
+            Freq::Daily | Freq::Weekly => {
+                // Over 7 steps, daily occurrences come up on each weekday
+                // once, just as weekly ones do in one step.
+                let days = match (self.freq, self.by_day.is_empty()) {
+                    (Freq::Daily, true) => interval,
+                    _ => 7 * interval,
+                };
+                let periods = ((today - date).num_days() - 1).div_euclid(days);
+                let end = u64::try_from(periods * days)
+                    .ok()
+                    .and_then(|days| date.checked_add_days(Days::new(days)));
+                (periods, end)
+            }
+            // Days up to the 28th are in every month, so each period has
+            // all of them.
+            Freq::Monthly
+                if date.day() <= 28
+                    && self.by_month_day.iter().all(|day| (1..=28).contains(day)) =>
+            {
+                let months = i64::from(today.year() - date.year()) * 12 + i64::from(today.month())
+                    - i64::from(date.month());
+                let periods = (months - 1).div_euclid(interval);
+                (periods, add_months(periods * interval))
+            }
+            Freq::Yearly if (date.month(), date.day()) != (2, 29) => {
+                let years = i64::from(today.year() - date.year());
+                let periods = (years - 1).div_euclid(interval);
+                (periods, add_months(periods * interval * 12))
+            }
+            Freq::Monthly | Freq::Yearly => return (date, 0),
+        };
+
+        match end {
+            Some(end) if periods > 0 => {
+                let skipped = u32::try_from(periods * per_period).unwrap_or(u32::MAX);
+                (end, skipped)
+            }
+            _ => (date, 0),
+        }
+    }
+
+    /// The next occurrence after `date` that isn't before `today`, along with
+    /// the rule the new occurrence should carry (with `COUNT` reduced by the
+    /// occurrences used up). `None` once the rule has run out.
+    pub(crate) fn next_after(
+        &self,
+        date: NaiveDate,
+        today: NaiveDate,
+    ) -> Option<(NaiveDate, Rule)> {
+        let mut rule = self.clone();
+        let (mut date, skipped) = self.skip_periods(date, today);
+        if let Some(count) = rule.count {
+            rule.count = Some(count.checked_sub(skipped).filter(|count| *count > 0)?);
+        }
+
+        loop {
+            if let Some(count) = rule.count {
+                if count <= 1 {
+                    return None;
+                }
+                rule.count = Some(count - 1);
+            }
+
+            date = rule.next_date(date)?;
+            if rule.until.is_some_and(|until| date > until) {
+                return None;
+            }
+            if date >= today {
+                return Some((date, rule));
+            }
+        }
+    }
+}
+
+fn ordinal(day: i32) -> String {
+    let suffix = match (day % 10, day % 100) {
+        (1, 11) | (2, 12) | (3, 13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", day, suffix)
+}
+
+/// Normalizes user input for a todo's rule: blank clears it, anything else
+/// must parse.
+pub(crate) fn parse_rrule(input: &str) -> Result<Option<String>, String> {
+    match input.trim() {
+        "" => Ok(None),
+        input if input.len() > MAX_RRULE_LEN => Err(format!(
+            "rules can be at most {} characters long",
+            MAX_RRULE_LEN
+        )),
+        input => input.parse::<Rule>().map(|rule| Some(rule.to_string())),
+    }
+}
+
+/// Creates the next occurrence of recurring todo `todo_id`, which `actor_id`
+/// has just completed. The rule moves to the new todo, so completing the old
+/// one again doesn't repeat it twice. Returns the new todo's id, if any.
+pub(crate) async fn create_next_occurrence(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+    actor_id: u32,
+    today: NaiveDate,
+) -> Result<Option<u32>, sqlx::Error> {
+    let Some(todo) = sqlx::query_as::<_, Todo>(
+        "
+            SELECT * FROM todos WHERE id=?
+        ",
+    )
+    .bind(todo_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let Some(rule) = todo
+        .rrule
+        .as_deref()
+        .and_then(|rrule| rrule.parse::<Rule>().ok())
+    else {
+        return Ok(None);
+    };
+
+    sqlx::query(
+        "
+            UPDATE todos SET rrule=NULL WHERE id=?
+        ",
+    )
+    .bind(todo_id)
+    .execute(&mut *conn)
+    .await?;
+
+    history::record_change(conn, todo_id, actor_id, "rrule", todo.rrule.clone(), None).await?;
+
+    let Some((due_date, rule)) = rule.next_after(todo.due_date.unwrap_or(today), today) else {
+        return Ok(None);
+    };
+
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+            INSERT INTO todos (name, list_id, due_date, due_time, parent_id, priority, rrule, position)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, (
+                SELECT COALESCE(MAX(position) + 1, 0) FROM todos
+                WHERE list_id = ?2 AND parent_id IS ?5
+            ))
+            RETURNING id
+        ",
+    )
+    .bind(&todo.name)
+    .bind(todo.list_id)
+    .bind(due_date)
+    .bind(todo.due_time)
+    .bind(todo.parent_id)
+    .bind(todo.priority)
+    .bind(rule.to_string())
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "
+            INSERT INTO todo_tags (todo_id, tag_id) SELECT ?, tag_id FROM todo_tags WHERE todo_id=?
+        ",
+    )
+    .bind(id)
+    .bind(todo_id)
+    .execute(&mut *conn)
+    .await?;
+
+    history::record(conn, id, Some(actor_id), history::Action::Created).await?;
+
+    Ok(Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// The next occurrence of `rrule` after `from`, counting from that day.
+    fn next(rrule: &str, from: NaiveDate) -> Option<(NaiveDate, String)> {
+        let rule = rrule.parse::<Rule>().unwrap();
+        rule.next_after(from, from)
+            .map(|(date, rule)| (date, rule.to_string()))
+    }
+
+    #[test]
+    fn month_days_skip_months_that_are_too_short() {
+        assert_eq!(
+            next("FREQ=MONTHLY;BYMONTHDAY=31", date(2024, 1, 31)),
+            Some((date(2024, 3, 31), "FREQ=MONTHLY;BYMONTHDAY=31".to_string()))
+        );
+        assert_eq!(
+            next("FREQ=MONTHLY;BYMONTHDAY=30", date(2024, 1, 30)).map(|(date, _)| date),
+            Some(date(2024, 3, 30))
+        );
+    }
+
+    #[test]
+    fn negative_month_days_count_from_the_end() {
+        assert_eq!(
+            next("FREQ=MONTHLY;BYMONTHDAY=-1", date(2024, 1, 31)).map(|(date, _)| date),
+            Some(date(2024, 2, 29))
+        );
+        assert_eq!(
+            next("FREQ=MONTHLY;BYMONTHDAY=-1", date(2023, 1, 31)).map(|(date, _)| date),
+            Some(date(2023, 2, 28))
+        );
+    }
+
+    #[test]
+    fn monthly_without_month_days_keeps_the_day() {
+        assert_eq!(
+            next("FREQ=MONTHLY;INTERVAL=3", date(2024, 11, 15)).map(|(date, _)| date),
+            Some(date(2025, 2, 15))
+        );
+    }
+
+    #[test]
+    fn count_runs_out() {
+        let (first, rule) = next("FREQ=DAILY;COUNT=3", date(2024, 1, 1)).unwrap();
+        assert_eq!(first, date(2024, 1, 2));
+        assert_eq!(rule, "FREQ=DAILY;COUNT=2");
+
+        let (second, rule) = next(&rule, first).unwrap();
+        assert_eq!(second, date(2024, 1, 3));
+        assert_eq!(rule, "FREQ=DAILY;COUNT=1");
+
+        assert_eq!(next(&rule, second), None);
+    }
+
+    #[test]
+    fn until_is_the_last_day() {
+        assert_eq!(
+            next("FREQ=DAILY;UNTIL=20240102", date(2024, 1, 1)).map(|(date, _)| date),
+            Some(date(2024, 1, 2))
+        );
+        assert_eq!(next("FREQ=DAILY;UNTIL=20240102", date(2024, 1, 2)), None);
+    }
+
+    #[test]
+    fn weekly_by_day_goes_to_the_next_listed_day() {
+        // 3 January 2024 is a Wednesday.
+        assert_eq!(
+            next("FREQ=WEEKLY;BYDAY=MO,WE,FR", date(2024, 1, 3)).map(|(date, _)| date),
+            Some(date(2024, 1, 5))
+        );
+        assert_eq!(
+            next("FREQ=WEEKLY;BYDAY=MO,WE", date(2024, 1, 3)).map(|(date, _)| date),
+            Some(date(2024, 1, 8))
+        );
+        assert_eq!(
+            next("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE", date(2024, 1, 3)).map(|(date, _)| date),
+            Some(date(2024, 1, 15))
+        );
+    }
+
+    #[test]
+    fn daily_by_day_skips_other_days() {
+        // 5 January 2024 is a Friday.
+        assert_eq!(
+            next("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR", date(2024, 1, 5)).map(|(date, _)| date),
+            Some(date(2024, 1, 8))
+        );
+    }
+
+    #[test]
+    fn missed_occurrences_are_skipped() {
+        let rule = "FREQ=WEEKLY".parse::<Rule>().unwrap();
+        let (date, _) = rule
+            .next_after(date(2024, 1, 1), date(2024, 1, 20))
+            .unwrap();
+        assert_eq!(date, NaiveDate::from_ymd_opt(2024, 1, 22).unwrap());
+    }
+
+    /// `next_after` the slow way, one occurrence at a time.
+    fn stepped(rule: &Rule, date: NaiveDate, today: NaiveDate) -> Option<(NaiveDate, String)> {
+        let mut rule = rule.clone();
+        let mut date = date;
+        loop {
+            if let Some(count) = rule.count {
+                if count <= 1 {
+                    return None;
+                }
+                rule.count = Some(count - 1);
+            }
+            date = rule.next_date(date)?;
+            if rule.until.is_some_and(|until| date > until) {
+                return None;
+            }
+            if date >= today {
+                return Some((date, rule.to_string()));
+            }
+        }
+    }
+
+    #[test]
+    fn missed_periods_are_jumped_over() {
+        let rules = [
+            "FREQ=DAILY",
+            "FREQ=DAILY;INTERVAL=3",
+            "FREQ=DAILY;COUNT=191",
+            "FREQ=DAILY;COUNT=192",
+            "FREQ=DAILY;UNTIL=20240611",
+            "FREQ=DAILY;INTERVAL=3;BYDAY=MO,TH",
+            "FREQ=DAILY;BYDAY=SA;COUNT=28",
+            "FREQ=WEEKLY;INTERVAL=2",
+            "FREQ=WEEKLY;INTERVAL=3;BYDAY=TU,SU",
+            "FREQ=WEEKLY;BYDAY=MO,FR;COUNT=55",
+            "FREQ=MONTHLY;INTERVAL=5",
+            "FREQ=MONTHLY;BYMONTHDAY=1,15,28;COUNT=20",
+            "FREQ=MONTHLY;BYMONTHDAY=-1",
+            "FREQ=YEARLY;INTERVAL=3",
+            "FREQ=YEARLY;COUNT=35",
+        ];
+        let today = date(2024, 6, 12);
+
+        for rrule in rules {
+            let rule = rrule.parse::<Rule>().unwrap();
+            for from in [
+                date(1990, 1, 31),
+                date(2019, 2, 28),
+                date(2023, 12, 5),
+                date(2024, 6, 11),
+                date(2024, 6, 12),
+            ] {
+                assert_eq!(
+                    rule.next_after(from, today)
+                        .map(|(date, rule)| (date, rule.to_string())),
+                    stepped(&rule, from, today),
+                    "{} from {}",
+                    rrule,
+                    from
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn yearly_on_a_leap_day_waits_for_the_next_one() {
+        assert_eq!(
+            next("FREQ=YEARLY", date(2024, 2, 29)).map(|(date, _)| date),
+            Some(date(2028, 2, 29))
+        );
+        // 2100, 2200 and 2300 aren't leap years.
+        assert_eq!(
+            next("FREQ=YEARLY;INTERVAL=25", date(2000, 2, 29)).map(|(date, _)| date),
+            Some(date(2400, 2, 29))
+        );
+    }
+
+    #[test]
+    fn daily_by_day_needs_the_weekday_to_change() {
+        assert!("FREQ=DAILY;INTERVAL=7;BYDAY=MO".parse::<Rule>().is_err());
+        assert!("FREQ=DAILY;INTERVAL=14;BYDAY=MO,FR"
+            .parse::<Rule>()
+            .is_err());
+        assert!("FREQ=DAILY;INTERVAL=6;BYDAY=MO".parse::<Rule>().is_ok());
+    }
+
+    #[test]
+    fn long_rules_are_rejected() {
+        let rule = format!("FREQ=WEEKLY;BYDAY={}", vec!["MO"; 100].join(","));
+        assert!(parse_rrule(&rule).is_err());
+        assert!(parse_rrule("FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR").is_ok());
+    }
+
+    #[test]
+    fn large_intervals_are_capped() {
+        assert!("FREQ=YEARLY;INTERVAL=2147483647".parse::<Rule>().is_err());
+        assert!("FREQ=MONTHLY;INTERVAL=4294967295".parse::<Rule>().is_err());
+        assert!("FREQ=DAILY;INTERVAL=1001".parse::<Rule>().is_err());
+
+        assert_eq!(
+            next("FREQ=YEARLY;INTERVAL=1000", date(2024, 2, 29)).map(|(date, _)| date),
+            Some(date(3024, 2, 29))
+        );
+        assert_eq!(
+            next(
+                "FREQ=MONTHLY;INTERVAL=1000;BYMONTHDAY=31",
+                date(2024, 1, 31)
+            )
+            .map(|(date, _)| date),
+            Some(date(2107, 5, 31))
+        );
+    }
+}
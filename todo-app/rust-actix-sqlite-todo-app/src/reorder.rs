@@ -0,0 +1,91 @@
+use actix_web::{post, web, Responder};
+use serde::Deserialize;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+
+use crate::{list_url, Change, CurrentUser, Error, State};
+
+/// Moves todo `todo_id` to index `position` among its siblings (clamped to
+/// their number) and renumbers the siblings to match. Returns the todo's
+/// list, or `None` if it doesn't exist.
+pub(crate) async fn set_position(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+    position: i64,
+) -> Result<Option<u32>, sqlx::Error> {
+    let Some((list_id, parent_id)) = sqlx::query_as::<_, (u32, Option<u32>)>(
+        "
+            SELECT list_id, parent_id FROM todos WHERE id=?
+        ",
+    )
+    .bind(todo_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let mut ids = sqlx::query_scalar::<_, u32>(
+        "
+            SELECT id FROM todos
+            WHERE list_id=? AND parent_id IS ? AND id != ? AND deleted_at IS NULL
+            ORDER BY position, id
+        ",
+    )
+    .bind(list_id)
+    .bind(parent_id)
+    .bind(todo_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let position = usize::try_from(position).unwrap_or(0).min(ids.len());
+    ids.insert(position, todo_id);
+
+    for (position, id) in ids.into_iter().enumerate() {
+        sqlx::query(
+            "
+                UPDATE todos SET position=? WHERE id=? AND position != ?
+            ",
+        )
+        .bind(position as i64)
+        .bind(id)
+        .bind(position as i64)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(Some(list_id))
+}
+
+#[derive(Deserialize)]
+struct MoveForm {
+    position: i64,
+}
+
+#[post("/todos/{id}/move")]
+async fn move_todo(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+    web::Form(form): web::Form<MoveForm>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+    let Some(list_id) = set_position(&mut tx, id, form.position).await? else {
+        return Err(Error::NotFound);
+    };
+    tx.commit().await?;
+
+    state.live.publish(user.id, Change::Updated, id, list_id);
+
+    Ok(web::Redirect::to(list_url(Some(list_id))).see_other())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(move_todo);
+}
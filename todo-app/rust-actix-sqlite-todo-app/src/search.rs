@@ -0,0 +1,137 @@
+use actix_web::{get, web};
+use maud::{html, Markup};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::{page, CurrentUser, Error, State};
+
+// Wrapped around matches by `highlight()`, so the name can still be escaped
+// piece by piece. Control characters like these don't turn up in todo names.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// Turns what the user typed into an FTS5 query. Words are matched as-is
+/// (a trailing `*` makes a prefix match), `"quoted text"` is matched as a
+/// phrase, and everything else is quoted so it can't be a syntax error.
+fn fts_query(input: &str) -> Option<String> {
+    let mut terms = Vec::new();
+
+    for (i, part) in input.split('"').enumerate() {
+        if i % 2 == 1 {
+            // Inside quotes.
+            if !part.trim().is_empty() {
+                terms.push(format!("\"{}\"", part.trim()));
+            }
+            continue;
+        }
+
+        for word in part.split_whitespace() {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(word) => (word, "*"),
+                None => (word, ""),
+            };
+
+            if !word.is_empty() {
+                terms.push(format!("\"{}\"{}", word, prefix));
+            }
+        }
+    }
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Renders `highlighted` (the output of `highlight()`) with matches in `mark`s.
+fn render_highlighted(highlighted: &str) -> Markup {
+    html! {
+        @for (i, part) in highlighted.split([MATCH_START, MATCH_END]).enumerate() {
+            @if i % 2 == 1 {
+                mark { (part) }
+            } @else {
+                (part)
+            }
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchResult {
+    done: bool,
+    list_id: u32,
+    list_name: String,
+    highlighted: String,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[get("/search")]
+async fn search(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    web::Query(query): web::Query<SearchQuery>,
+) -> Result<Markup, Error> {
+    let results = match fts_query(&query.q) {
+        Some(fts_query) => {
+            sqlx::query_as::<_, SearchResult>(
+                "
+                    SELECT todos.done, todos.list_id, lists.name AS list_name,
+                        highlight(todos_fts, 0, char(2), char(3)) AS highlighted
+                    FROM todos_fts
+                    JOIN todos ON todos.id = todos_fts.rowid
+                    JOIN lists ON lists.id = todos.list_id
+                    WHERE todos_fts MATCH ? AND lists.user_id = ? AND todos.deleted_at IS NULL
+                    ORDER BY rank
+                ",
+            )
+            .bind(fts_query)
+            .bind(user.id)
+            .fetch_all(&state.db)
+            .await?
+        }
+        None => vec![],
+    };
+
+    Ok(page(
+        "Search",
+        html! {
+            nav class="lists" {
+                a href="/" { "← Back to lists" }
+            }
+            h1 { "Search" }
+            (search_form(&query.q))
+            @if !query.q.trim().is_empty() {
+                p class="search-summary" {
+                    (results.len()) " result" @if results.len() != 1 { "s" } " for “" (query.q) "”"
+                }
+            }
+            ul class="search-results" {
+                @for result in &results {
+                    li.completed[result.done] {
+                        span class="text" { (render_highlighted(&result.highlighted)) }
+                        a class="list-link" href=(format!("/lists/{}", result.list_id)) { (result.list_name) }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// The search box shown on the list and search pages.
+pub(crate) fn search_form(q: &str) -> Markup {
+    html! {
+        form class="search" action="/search" method="GET" {
+            input type="search" name="q" value=(q) placeholder="Search todos... (\"phrase\", prefix*)" aria-label="Search todos";
+        }
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(search);
+}
@@ -0,0 +1,154 @@
+use actix_web::{post, web, Responder};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{bulk, list_url, Change, CurrentUser, Error, State};
+
+/// How many of a todo's subtasks (at any depth) are done.
+#[derive(Clone, Copy)]
+pub(crate) struct Progress {
+    pub(crate) done: u32,
+    pub(crate) total: u32,
+}
+
+/// Progress of every todo in list `list_id` that has subtasks.
+pub(crate) async fn load_progress(
+    db: &SqlitePool,
+    list_id: u32,
+) -> Result<HashMap<u32, Progress>, sqlx::Error> {
+    // Pairs every subtask with each of its ancestors.
+    let rows = sqlx::query_as::<_, (u32, u32, u32)>(
+        "
+            WITH RECURSIVE ancestors(ancestor_id, done) AS (
+                SELECT parent_id, done FROM todos
+                WHERE list_id = ? AND parent_id IS NOT NULL AND deleted_at IS NULL
+                UNION ALL
+                SELECT todos.parent_id, ancestors.done FROM ancestors
+                JOIN todos ON todos.id = ancestors.ancestor_id
+                WHERE todos.parent_id IS NOT NULL
+            )
+            SELECT ancestor_id, SUM(done), COUNT(*) FROM ancestors GROUP BY ancestor_id
+        ",
+    )
+    .bind(list_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, done, total)| (id, Progress { done, total }))
+        .collect())
+}
+
+/// Number of unfinished subtasks of `todo_id`, at any depth.
+pub(crate) async fn count_open_subtasks(db: &SqlitePool, todo_id: u32) -> Result<u32, sqlx::Error> {
+    sqlx::query_scalar::<_, u32>(
+        "
+            WITH RECURSIVE subtasks(id, done) AS (
+                SELECT id, done FROM todos WHERE parent_id = ? AND deleted_at IS NULL
+                UNION ALL
+                SELECT todos.id, todos.done FROM todos JOIN subtasks ON todos.parent_id = subtasks.id
+                WHERE todos.deleted_at IS NULL
+            )
+            SELECT COUNT(*) FROM subtasks WHERE NOT done
+        ",
+    )
+    .bind(todo_id)
+    .fetch_one(db)
+    .await
+}
+
+/// Moves `todo_id` and all of its subtasks to list `list_id`. The todo is
+/// detached from its parent, which stays behind in the old list, and goes to
+/// the end of the new one.
+pub(crate) async fn move_to_list(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+    list_id: u32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+            WITH RECURSIVE subtree(id) AS (
+                SELECT ?1
+                UNION ALL
+                SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+            )
+            UPDATE todos
+            SET list_id = ?2,
+                parent_id = CASE WHEN id = ?1 THEN NULL ELSE parent_id END,
+                position = CASE WHEN id = ?1 THEN (
+                    SELECT COALESCE(MAX(position) + 1, 0) FROM todos
+                    WHERE list_id = ?2 AND parent_id IS NULL
+                ) ELSE position END
+            WHERE id IN subtree AND list_id != ?2
+        ",
+    )
+    .bind(todo_id)
+    .bind(list_id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+#[post("/todos/{id}/complete-subtasks")]
+async fn complete_subtasks(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    // Collected first, so the next occurrences of recurring subtasks stay
+    // open.
+    let open = sqlx::query_scalar::<_, u32>(
+        "
+            WITH RECURSIVE subtasks(id) AS (
+                SELECT id FROM todos WHERE parent_id = ? AND deleted_at IS NULL
+                UNION ALL
+                SELECT todos.id FROM todos JOIN subtasks ON todos.parent_id = subtasks.id
+                WHERE todos.deleted_at IS NULL
+            )
+            SELECT id FROM todos WHERE id IN subtasks AND NOT done
+        ",
+    )
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut changes = Vec::new();
+    for subtask_id in open {
+        if let Some((list_id, next)) = bulk::complete(&mut tx, subtask_id, user.id).await? {
+            changes.push((Change::Updated, subtask_id, list_id));
+            changes.extend(next.map(|next| (Change::Created, next, list_id)));
+        }
+    }
+
+    tx.commit().await?;
+
+    for (change, id, list_id) in changes {
+        state.live.publish(user.id, change, id, list_id);
+    }
+
+    let list_id = sqlx::query_scalar::<_, u32>(
+        "
+            SELECT list_id FROM todos WHERE id=?
+        ",
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await?;
+
+    Ok(web::Redirect::to(list_url(list_id)).see_other())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(complete_subtasks);
+}
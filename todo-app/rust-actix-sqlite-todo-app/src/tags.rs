@@ -0,0 +1,154 @@
+use actix_session::Session;
+use actix_web::{post, web, HttpRequest, Responder};
+use serde::Deserialize;
+use sqlx::{QueryBuilder, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{history, items, Change, CurrentUser, Error, State, Todo};
+
+/// Splits user input like `"@phone, @office errands"` into distinct tag names.
+pub(crate) fn parse_tags(input: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+
+    for tag in input.split(|c: char| c == ',' || c.is_whitespace()) {
+        if !tag.is_empty() && !tags.iter().any(|existing| existing == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+
+    tags
+}
+
+/// Fills in `tags` for each of `todos`.
+pub(crate) async fn load_tags(db: &SqlitePool, todos: &mut [Todo]) -> Result<(), sqlx::Error> {
+    if todos.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::new(
+        "
+            SELECT todo_tags.todo_id, tags.name FROM todo_tags
+            JOIN tags ON tags.id = todo_tags.tag_id
+            WHERE todo_tags.todo_id IN (
+        ",
+    );
+    let mut ids = query.separated(", ");
+    for todo in todos.iter() {
+        ids.push_bind(todo.id);
+    }
+    ids.push_unseparated(") ORDER BY tags.name");
+
+    let rows = query
+        .build_query_as::<(u32, String)>()
+        .fetch_all(db)
+        .await?;
+
+    let mut tags_by_todo: HashMap<u32, Vec<String>> = HashMap::new();
+    for (todo_id, name) in rows {
+        tags_by_todo.entry(todo_id).or_default().push(name);
+    }
+
+    for todo in todos {
+        todo.tags = tags_by_todo.remove(&todo.id).unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+/// Replaces the tags on todo `todo_id` with `tags`, creating any of `user_id`'s
+/// tags that don't exist yet and dropping ones that are no longer used.
+pub(crate) async fn set_tags(
+    conn: &mut SqliteConnection,
+    user_id: u32,
+    todo_id: u32,
+    tags: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+            DELETE FROM todo_tags WHERE todo_id=?
+        ",
+    )
+    .bind(todo_id)
+    .execute(&mut *conn)
+    .await?;
+
+    for tag in tags {
+        sqlx::query(
+            "
+                INSERT INTO tags (user_id, name) VALUES (?, ?) ON CONFLICT DO NOTHING
+            ",
+        )
+        .bind(user_id)
+        .bind(tag)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            "
+                INSERT INTO todo_tags (todo_id, tag_id)
+                SELECT ?, id FROM tags WHERE user_id=? AND name=?
+            ",
+        )
+        .bind(todo_id)
+        .bind(user_id)
+        .bind(tag)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query(
+        "
+            DELETE FROM tags WHERE user_id=? AND id NOT IN (SELECT tag_id FROM todo_tags)
+        ",
+    )
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct TagsForm {
+    tags: String,
+}
+
+#[post("/todos/{id}/tags")]
+async fn update_todo_tags(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    request: HttpRequest,
+    id: web::Path<u32>,
+    web::Form(form): web::Form<TagsForm>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+    let before = history::snapshot(&mut tx, id).await?;
+    set_tags(&mut tx, user.id, id, &parse_tags(&form.tags)).await?;
+    let after = history::snapshot(&mut tx, id).await?;
+    if let (Some(before), Some(after)) = (&before, &after) {
+        history::record_changes(&mut tx, user.id, before, after).await?;
+    }
+    tx.commit().await?;
+
+    let Some(after) = after else {
+        return Err(Error::NotFound);
+    };
+
+    state
+        .live
+        .publish(user.id, Change::Updated, id, after.list_id);
+
+    items::reply(&state, &request, &session, after.list_id, &[id], None).await
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(update_todo_tags);
+}
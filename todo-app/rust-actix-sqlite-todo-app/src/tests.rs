@@ -0,0 +1,931 @@
+//! Drives the whole app, middleware included, through `actix_web::test`
+//! against a fresh in-memory database per test.
+
+use actix_http::Request;
+use actix_web::{
+    body::{self, MessageBody},
+    cookie::{Cookie, Key},
+    dev::{Service, ServiceResponse},
+    http::{header, StatusCode},
+    rt, test,
+};
+use sqlx::sqlite::SqlitePoolOptions;
+use std::future::poll_fn;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{app, State};
+
+/// A migrated in-memory database. Every connection to `sqlite::memory:` gets
+/// a database of its own, so the pool keeps exactly one, for good.
+async fn test_state() -> Arc<State> {
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("in-memory database");
+    sqlx::migrate!().run(&db).await.expect("migrations");
+
+    Arc::new(State::new(db))
+}
+
+/// Something that can answer requests the way `test::init_service` does.
+trait TestApp:
+    Service<Request, Response = ServiceResponse<Self::Body>, Error = actix_web::Error>
+{
+    type Body: MessageBody + 'static;
+}
+
+impl<S, B> TestApp for S
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    type Body = B;
+}
+
+/// A response, read into memory.
+struct Page {
+    status: StatusCode,
+    location: Option<String>,
+    body: String,
+}
+
+/// Keeps the session cookie and CSRF token between requests, like a browser
+/// with one tab open.
+#[derive(Default)]
+struct Browser {
+    session: Option<Cookie<'static>>,
+    csrf_token: Option<String>,
+}
+
+impl Browser {
+    /// A browser logged in as a new user, `username`.
+    async fn register(app: &impl TestApp, username: &str) -> Self {
+        let mut browser = Browser::default();
+        browser.get(app, "/register").await;
+        let page = browser
+            .post(
+                app,
+                "/register",
+                &[("username", username), ("password", "correct horse")],
+            )
+            .await;
+        assert_eq!(page.status, StatusCode::SEE_OTHER, "{}", page.body);
+        browser
+    }
+
+    async fn send(&mut self, app: &impl TestApp, request: test::TestRequest) -> Page {
+        let request = match &self.session {
+            Some(cookie) => request.cookie(cookie.clone()),
+            None => request,
+        };
+        // Errors from middleware, like a missing CSRF token, are answered
+        // the way the server would.
+        let response = match app.call(request.to_request()).await {
+            Ok(response) => response.into_parts().1.map_into_boxed_body(),
+            Err(err) => err.error_response(),
+        };
+
+        if let Some(cookie) = response.cookies().next() {
+            self.session = Some(cookie.into_owned());
+        }
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        // Pages carry the token in every form, so remember it for the next one.
+        if let Some(token) = csrf_token(&body) {
+            self.csrf_token = Some(token);
+        }
+
+        Page {
+            status,
+            location,
+            body,
+        }
+    }
+
+    async fn get(&mut self, app: &impl TestApp, uri: &str) -> Page {
+        self.send(app, test::TestRequest::get().uri(uri)).await
+    }
+
+    /// Submits a form the way the page would, CSRF token included.
+    async fn post(&mut self, app: &impl TestApp, uri: &str, fields: &[(&str, &str)]) -> Page {
+        let token = self.csrf_token.clone().expect("a page with a form first");
+        let mut fields = fields.to_vec();
+        fields.push(("csrf_token", &token));
+
+        self.send(app, test::TestRequest::post().uri(uri).set_form(fields))
+            .await
+    }
+
+    /// Submits a `multipart/form-data` form with a file, `data`, in field
+    /// `file`, CSRF token included.
+    async fn upload(
+        &mut self,
+        app: &impl TestApp,
+        uri: &str,
+        fields: &[(&str, &str)],
+        data: &str,
+    ) -> Page {
+        const BOUNDARY: &str = "test-boundary";
+
+        let token = self.csrf_token.clone().expect("a page with a form first");
+        let mut body = String::new();
+        for (name, value) in fields.iter().chain([&("csrf_token", token.as_str())]) {
+            body += &format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                BOUNDARY, name, value
+            );
+        }
+        body += &format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\n\
+             Content-Type: text/plain\r\n\r\n{}\r\n--{}--\r\n",
+            BOUNDARY, data, BOUNDARY
+        );
+
+        let request = test::TestRequest::post()
+            .uri(uri)
+            .insert_header((
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(body);
+        self.send(app, request).await
+    }
+}
+
+/// The token in the first hidden `csrf_token` field of `body`.
+fn csrf_token(body: &str) -> Option<String> {
+    const FIELD: &str = r#"name="csrf_token" value=""#;
+    let start = body.find(FIELD)? + FIELD.len();
+    let end = start + body[start..].find('"')?;
+    Some(body[start..end].to_string())
+}
+
+/// The `li` of todo `id`, up to the start of its contents.
+fn todo_tag(body: &str, id: u32) -> Option<&str> {
+    let marker = format!(r#" data-id="{}""#, id);
+    let at = body.find(&marker)?;
+    let start = body[..at].rfind("<li")?;
+    let end = at + body[at..].find('>')?;
+    Some(&body[start..=end])
+}
+
+#[actix_web::test]
+async fn index_redirects_to_login_without_a_session() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+
+    let page = Browser::default().get(&app, "/").await;
+
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+    assert_eq!(page.location.as_deref(), Some("/login"));
+}
+
+#[actix_web::test]
+async fn index_renders_the_first_list() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+
+    let page = browser.get(&app, "/").await;
+
+    assert_eq!(page.status, StatusCode::OK);
+    assert!(page.body.starts_with("<!DOCTYPE html>"));
+    assert!(page.body.contains("<h1>Todos</h1>"));
+    assert!(page
+        .body
+        .contains(r#"<form class="new-todo-form" action="/todos/new" method="POST">"#));
+    assert!(page
+        .body
+        .contains(r#"<span class="remaining">0 items left</span>"#));
+}
+
+#[actix_web::test]
+async fn new_todo_is_added_to_the_list() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+
+    let page = browser
+        .post(
+            &app,
+            "/todos/new",
+            &[("name", "Buy milk"), ("list_id", "1")],
+        )
+        .await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+    assert_eq!(page.location.as_deref(), Some("/lists/1"));
+
+    let page = browser.get(&app, "/lists/1").await;
+    assert_eq!(
+        todo_tag(&page.body, 1),
+        Some(r#"<li class="" data-id="1" draggable="true">"#)
+    );
+    assert!(page
+        .body
+        .contains(r#"<form action="/todos/1/toggle-completion" method="POST">"#));
+    assert!(page
+        .body
+        .contains(r#"<button class="text">Buy milk</button>"#));
+    assert!(page
+        .body
+        .contains(r#"<span class="remaining">1 item left</span>"#));
+}
+
+#[actix_web::test]
+async fn new_todo_names_are_escaped() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+
+    browser
+        .post(
+            &app,
+            "/todos/new",
+            &[("name", "<script>alert(1)</script>"), ("list_id", "1")],
+        )
+        .await;
+
+    let page = browser.get(&app, "/").await;
+    assert!(page
+        .body
+        .contains(r#"<button class="text">&lt;script&gt;alert(1)&lt;/script&gt;</button>"#));
+    assert!(!page.body.contains("<script>alert(1)"));
+}
+
+#[actix_web::test]
+async fn new_todo_answers_fragment_requests_with_the_new_item() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+    let token = browser.csrf_token.clone().unwrap();
+
+    let request = test::TestRequest::post()
+        .uri("/todos/new")
+        .insert_header(("HX-Request", "true"))
+        .set_form([
+            ("name", "Buy milk"),
+            ("list_id", "1"),
+            ("csrf_token", &token),
+        ]);
+    let page = browser.send(&app, request).await;
+
+    assert_eq!(page.status, StatusCode::OK);
+    assert!(!page.body.contains("<!DOCTYPE html>"));
+    assert!(todo_tag(&page.body, 1).is_some());
+    assert!(page
+        .body
+        .contains(r#"<button class="text">Buy milk</button>"#));
+}
+
+#[actix_web::test]
+async fn new_todo_in_an_unknown_list_is_not_found() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+
+    let page = browser
+        .post(
+            &app,
+            "/todos/new",
+            &[("name", "Buy milk"), ("list_id", "42")],
+        )
+        .await;
+
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+    assert!(page.body.contains("That doesn't exist, or not any more"));
+}
+
+#[actix_web::test]
+async fn new_todo_without_a_name_is_rejected() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+
+    let page = browser.post(&app, "/todos/new", &[("list_id", "1")]).await;
+
+    assert_eq!(page.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[actix_web::test]
+async fn new_todo_with_a_blank_name_is_rejected() {
+    let state = test_state().await;
+    let app = test::init_service(app(state.clone(), Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+    let token = browser.csrf_token.clone().unwrap();
+
+    // Spelled out, so the name is really there and only spaces.
+    let request = test::TestRequest::post()
+        .uri("/todos/new")
+        .insert_header(header::ContentType::form_url_encoded())
+        .set_payload(format!("name=%20%20&list_id=1&csrf_token={}", token));
+    let page = browser.send(&app, request).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+
+    let page = browser.get(&app, "/").await;
+    assert!(page
+        .body
+        .contains(r#"<p class="error">A todo needs a name</p>"#));
+    let todos = sqlx::query_scalar::<_, u32>(
+        "
+            SELECT COUNT(*) FROM todos
+        ",
+    )
+    .fetch_one(&state.db)
+    .await
+    .unwrap();
+    assert_eq!(todos, 0);
+}
+
+#[actix_web::test]
+async fn new_todo_without_a_csrf_token_is_forbidden() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+
+    let request = test::TestRequest::post()
+        .uri("/todos/new")
+        .set_form([("name", "Buy milk"), ("list_id", "1")]);
+    let page = browser.send(&app, request).await;
+
+    assert_eq!(page.status, StatusCode::FORBIDDEN);
+    let page = browser.get(&app, "/").await;
+    assert!(todo_tag(&page.body, 1).is_none());
+}
+
+#[actix_web::test]
+async fn toggling_completes_and_reopens_a_todo() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+    browser
+        .post(
+            &app,
+            "/todos/new",
+            &[("name", "Buy milk"), ("list_id", "1")],
+        )
+        .await;
+
+    let page = browser.post(&app, "/todos/1/toggle-completion", &[]).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+    let page = browser.get(&app, "/").await;
+    assert_eq!(
+        todo_tag(&page.body, 1),
+        Some(r#"<li class="completed" data-id="1" draggable="true">"#)
+    );
+    assert!(page
+        .body
+        .contains(r#"<span class="remaining">0 items left</span>"#));
+
+    browser.post(&app, "/todos/1/toggle-completion", &[]).await;
+    let page = browser.get(&app, "/").await;
+    assert_eq!(
+        todo_tag(&page.body, 1),
+        Some(r#"<li class="" data-id="1" draggable="true">"#)
+    );
+    assert!(page
+        .body
+        .contains(r#"<span class="remaining">1 item left</span>"#));
+}
+
+#[actix_web::test]
+async fn toggling_an_unknown_todo_is_not_found() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+
+    let page = browser.post(&app, "/todos/42/toggle-completion", &[]).await;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+
+    let page = browser
+        .post(&app, "/todos/not-a-number/toggle-completion", &[])
+        .await;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn deleting_moves_a_todo_to_the_trash() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+    browser
+        .post(
+            &app,
+            "/todos/new",
+            &[("name", "Buy milk"), ("list_id", "1")],
+        )
+        .await;
+
+    let page = browser.post(&app, "/todos/1/delete", &[]).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+
+    let page = browser.get(&app, "/").await;
+    assert!(todo_tag(&page.body, 1).is_none());
+    assert!(page
+        .body
+        .contains(r#"<form action="/todos/1/restore" method="POST">"#));
+    let page = browser.get(&app, "/trash").await;
+    assert!(page.body.contains("Buy milk"));
+
+    // It's gone from the list, so it can't be deleted again.
+    let page = browser.post(&app, "/todos/1/delete", &[]).await;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn flashes_about_long_named_todos_fit_in_the_session() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let name = "milk ".repeat(2000);
+    let mut browser = browser_with_todos(&app, &[name.trim()]).await;
+
+    let page = browser.post(&app, "/todos/1/delete", &[]).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+    let page = browser.get(&app, "/").await;
+    assert!(page.body.contains(&format!("Deleted “{}”.", name.trim())));
+
+    let page = browser.post(&app, "/todos/1/restore", &[]).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+    let page = browser
+        .post(
+            &app,
+            "/todos/1/edit",
+            &[("name", &name), ("rrule", "FREQ=HOURLY")],
+        )
+        .await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+    let page = browser.get(&app, "/").await;
+    assert!(page.body.contains("Invalid repeat rule"));
+}
+
+#[actix_web::test]
+async fn deleting_an_unknown_todo_is_not_found() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+
+    let page = browser.post(&app, "/todos/42/delete", &[]).await;
+
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn other_users_todos_are_forbidden() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut alice = Browser::register(&app, "alice").await;
+    alice.get(&app, "/").await;
+    alice
+        .post(
+            &app,
+            "/todos/new",
+            &[("name", "Buy milk"), ("list_id", "1")],
+        )
+        .await;
+    let mut bob = Browser::register(&app, "bob").await;
+    bob.get(&app, "/").await;
+
+    let page = bob.post(&app, "/todos/1/toggle-completion", &[]).await;
+    assert_eq!(page.status, StatusCode::FORBIDDEN);
+    let page = bob.post(&app, "/todos/1/delete", &[]).await;
+    assert_eq!(page.status, StatusCode::FORBIDDEN);
+
+    let page = alice.get(&app, "/").await;
+    assert_eq!(
+        todo_tag(&page.body, 1),
+        Some(r#"<li class="" data-id="1" draggable="true">"#)
+    );
+}
+
+#[actix_web::test]
+async fn unknown_routes_are_not_found() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+
+    let page = browser.get(&app, "/nowhere").await;
+
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+    assert!(page.body.contains("<!DOCTYPE html>"));
+}
+
+/// A browser logged in as alice, with todos `names` (ids 1, 2, …) in her
+/// first list.
+async fn browser_with_todos(app: &impl TestApp, names: &[&str]) -> Browser {
+    let mut browser = Browser::register(app, "alice").await;
+    browser.get(app, "/").await;
+    for name in names {
+        browser
+            .post(app, "/todos/new", &[("name", name), ("list_id", "1")])
+            .await;
+    }
+    browser
+}
+
+#[actix_web::test]
+async fn index_renders_a_checkbox_per_todo() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Buy milk"]).await;
+
+    let page = browser.get(&app, "/").await;
+
+    assert!(page.body.contains(
+        r#"<input type="checkbox" class="select" name="ids" value="1" form="bulk" aria-label="Select todo">"#
+    ));
+    assert!(page
+        .body
+        .contains(r#"<form id="bulk" action="/todos/bulk" method="POST">"#));
+}
+
+#[actix_web::test]
+async fn complete_all_completes_every_open_todo() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Buy milk", "Call mum"]).await;
+
+    let page = browser
+        .post(&app, "/todos/complete-all", &[("list_id", "1")])
+        .await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+
+    let page = browser.get(&app, "/").await;
+    for id in [1, 2] {
+        assert_eq!(
+            todo_tag(&page.body, id),
+            Some(
+                format!(
+                    r#"<li class="completed" data-id="{}" draggable="true">"#,
+                    id
+                )
+                .as_str()
+            )
+        );
+    }
+    assert!(page
+        .body
+        .contains(r#"<span class="remaining">0 items left</span>"#));
+}
+
+#[actix_web::test]
+async fn clear_completed_moves_completed_todos_to_the_trash() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Buy milk", "Call mum"]).await;
+    browser.post(&app, "/todos/1/toggle-completion", &[]).await;
+
+    let page = browser
+        .post(&app, "/todos/clear-completed", &[("list_id", "1")])
+        .await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+
+    let page = browser.get(&app, "/").await;
+    assert!(todo_tag(&page.body, 1).is_none());
+    assert!(todo_tag(&page.body, 2).is_some());
+    assert!(page.body.contains("Moved 1 todo to the trash."));
+}
+
+#[actix_web::test]
+async fn bulk_actions_apply_to_the_selected_todos() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Buy milk", "Call mum", "Water plants"]).await;
+
+    let page = browser
+        .post(
+            &app,
+            "/todos/bulk",
+            &[
+                ("list_id", "1"),
+                ("ids", "1"),
+                ("ids", "3"),
+                ("action", "complete"),
+            ],
+        )
+        .await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+    let page = browser.get(&app, "/").await;
+    assert!(todo_tag(&page.body, 1).unwrap().contains("completed"));
+    assert!(!todo_tag(&page.body, 2).unwrap().contains("completed"));
+    assert!(todo_tag(&page.body, 3).unwrap().contains("completed"));
+
+    browser
+        .post(
+            &app,
+            "/todos/bulk",
+            &[
+                ("list_id", "1"),
+                ("ids", "1"),
+                ("ids", "2"),
+                ("action", "delete"),
+            ],
+        )
+        .await;
+    let page = browser.get(&app, "/").await;
+    assert!(todo_tag(&page.body, 1).is_none());
+    assert!(todo_tag(&page.body, 2).is_none());
+    assert!(todo_tag(&page.body, 3).is_some());
+    assert!(page.body.contains("Moved 2 todos to the trash."));
+}
+
+#[actix_web::test]
+async fn bulk_move_moves_the_selected_todos_to_another_list() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Buy milk", "Call mum"]).await;
+    let page = browser.post(&app, "/lists/new", &[("name", "Work")]).await;
+    assert_eq!(page.location.as_deref(), Some("/lists/2"));
+    browser.get(&app, "/lists/1").await;
+
+    browser
+        .post(
+            &app,
+            "/todos/bulk",
+            &[
+                ("list_id", "1"),
+                ("ids", "2"),
+                ("action", "move"),
+                ("to_list_id", "2"),
+            ],
+        )
+        .await;
+
+    let page = browser.get(&app, "/lists/1").await;
+    assert!(todo_tag(&page.body, 1).is_some());
+    assert!(todo_tag(&page.body, 2).is_none());
+    let page = browser.get(&app, "/lists/2").await;
+    assert!(page
+        .body
+        .contains(r#"<button class="text">Call mum</button>"#));
+}
+
+#[actix_web::test]
+async fn bulk_actions_need_a_selection_and_known_todos() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Buy milk"]).await;
+
+    let page = browser
+        .post(
+            &app,
+            "/todos/bulk",
+            &[("list_id", "1"), ("action", "delete")],
+        )
+        .await;
+    assert_eq!(page.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(page.body.contains("Select some todos first"));
+
+    // Nothing changes if any of the todos is missing.
+    let page = browser
+        .post(
+            &app,
+            "/todos/bulk",
+            &[
+                ("list_id", "1"),
+                ("ids", "1"),
+                ("ids", "42"),
+                ("action", "delete"),
+            ],
+        )
+        .await;
+    assert_eq!(page.status, StatusCode::NOT_FOUND);
+    let page = browser.get(&app, "/").await;
+    assert!(todo_tag(&page.body, 1).is_some());
+}
+
+#[actix_web::test]
+async fn purged_todo_ids_are_not_reused() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Secret plans"]).await;
+    browser.post(&app, "/todos/1/delete", &[]).await;
+    let page = browser.post(&app, "/todos/1/purge", &[]).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+
+    browser
+        .post(
+            &app,
+            "/todos/new",
+            &[("name", "Buy milk"), ("list_id", "1")],
+        )
+        .await;
+
+    let page = browser.get(&app, "/").await;
+    assert!(todo_tag(&page.body, 1).is_none());
+    assert!(todo_tag(&page.body, 2).is_some());
+    // The new todo's history starts from scratch.
+    let page = browser.get(&app, "/todos/2/history").await;
+    assert!(page.body.contains("Created “Buy milk”"));
+    assert!(!page.body.contains("Secret plans"));
+}
+
+/// `browser`'s todos as exported to JSON, without the ids, which differ
+/// between users.
+async fn exported_todos(app: &impl TestApp, browser: &mut Browser) -> Vec<serde_json::Value> {
+    let page = browser.get(app, "/export?format=json").await;
+    let mut todos: Vec<serde_json::Value> = serde_json::from_str(&page.body).unwrap();
+    for todo in &mut todos {
+        let todo = todo.as_object_mut().unwrap();
+        todo.remove("id");
+        todo.remove("parent_id");
+    }
+    todos
+}
+
+#[actix_web::test]
+async fn todotxt_exports_import_unchanged() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let names = [
+        "x marks the spot",
+        "(B) plan",
+        "2024-01-01 retrospective",
+        "Read +foo and @bar",
+        "Show due:diligence at:once tag:along",
+        r"Back up \\server\share",
+    ];
+    let mut alice = browser_with_todos(&app, &names).await;
+    alice.post(&app, "/todos/1/toggle-completion", &[]).await;
+    alice
+        .post(
+            &app,
+            "/todos/2/tags",
+            &[("tags", r"phone @office +extra @ a:b \x")],
+        )
+        .await;
+    alice
+        .post(&app, "/lists/new", &[("name", r"sprint_42 \ q3")])
+        .await;
+    alice
+        .post(&app, "/todos/new", &[("name", "Ship it"), ("list_id", "2")])
+        .await;
+    let exported = alice.get(&app, "/export?format=todotxt").await.body;
+
+    let mut bob = Browser::register(&app, "bob").await;
+    bob.get(&app, "/import").await;
+    let page = bob
+        .upload(
+            &app,
+            "/import",
+            &[("format", "todotxt"), ("list_id", "3")],
+            &exported,
+        )
+        .await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER, "{}", page.body);
+
+    let page = bob.get(&app, "/export?format=todotxt").await;
+    assert_eq!(page.body, exported);
+    assert_eq!(
+        exported_todos(&app, &mut bob).await,
+        exported_todos(&app, &mut alice).await
+    );
+    assert!(exported.starts_with("x \\x marks the spot +Todos\n"));
+    assert!(exported.contains("(B) plan +Todos +extra tag:@ @office tag:\\x tag:a:b tag:phone\n"));
+    assert!(exported.contains("Ship it +sprint\\_42_\\\\_q3\n"));
+}
+
+#[actix_web::test]
+async fn completing_and_moving_a_recurring_todo_publishes_the_next_one_to_the_new_list() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+    browser.post(&app, "/lists/new", &[("name", "Work")]).await;
+    let session = browser.session.clone().unwrap();
+
+    let request = test::TestRequest::post()
+        .uri("/api/v1/todos")
+        .cookie(session.clone())
+        .set_json(serde_json::json!({
+            "name": "Water the plants",
+            "list_id": 1,
+            "due_date": "2024-01-01",
+            "rrule": "FREQ=DAILY",
+        }));
+    let response = app.call(request.to_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let events = test::TestRequest::get()
+        .uri("/events")
+        .cookie(session.clone())
+        .to_request();
+    let mut events = Box::pin(app.call(events).await.unwrap().into_body());
+
+    let request = test::TestRequest::patch()
+        .uri("/api/v1/todos/1")
+        .cookie(session)
+        .set_json(serde_json::json!({ "done": true, "list_id": 2 }));
+    let response = app.call(request.to_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The events were published during the PATCH, so they're waiting,
+    // between keep-alive comments.
+    let mut received = String::new();
+    while received.matches("event:").count() < 3 {
+        let chunk = rt::time::timeout(
+            Duration::from_secs(1),
+            poll_fn(|cx| events.as_mut().poll_next(cx)),
+        )
+        .await
+        .expect("another event");
+        received += std::str::from_utf8(&chunk.unwrap().ok().unwrap()).unwrap();
+    }
+    assert!(received.contains("event: deleted\ndata: {\"todo_id\":1,\"list_id\":1}"));
+    assert!(received.contains("event: created\ndata: {\"todo_id\":1,\"list_id\":2}"));
+    assert!(received.contains("event: created\ndata: {\"todo_id\":2,\"list_id\":2}"));
+}
+
+#[actix_web::test]
+async fn completing_subtasks_repeats_recurring_ones() {
+    let state = test_state().await;
+    let app = test::init_service(app(state.clone(), Key::generate())).await;
+    let mut browser = browser_with_todos(&app, &["Clean the house"]).await;
+    browser
+        .post(
+            &app,
+            "/todos/new",
+            &[
+                ("name", "Water the plants"),
+                ("list_id", "1"),
+                ("parent_id", "1"),
+                ("due_date", "2024-01-01"),
+                ("rrule", "FREQ=WEEKLY"),
+            ],
+        )
+        .await;
+
+    browser.post(&app, "/todos/1/toggle-completion", &[]).await;
+    let page = browser.post(&app, "/todos/1/complete-subtasks", &[]).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER);
+
+    let todos = sqlx::query_as::<_, (u32, bool, Option<u32>, Option<String>)>(
+        "
+            SELECT id, done, parent_id, rrule FROM todos ORDER BY id
+        ",
+    )
+    .fetch_all(&state.db)
+    .await
+    .unwrap();
+    assert_eq!(
+        todos,
+        [
+            (1, true, None, None),
+            (2, true, Some(1), None),
+            (3, false, Some(1), Some("FREQ=WEEKLY".to_string())),
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn api_names_are_trimmed() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/").await;
+    let session = browser.session.clone().unwrap();
+
+    let request = test::TestRequest::post()
+        .uri("/api/v1/todos")
+        .cookie(session.clone())
+        .set_json(serde_json::json!({ "name": "  Buy milk\t" }));
+    let todo: serde_json::Value = test::call_and_read_body_json(&app, request.to_request()).await;
+    assert_eq!(todo["name"], "Buy milk");
+
+    let request = test::TestRequest::patch()
+        .uri("/api/v1/todos/1")
+        .cookie(session)
+        .set_json(serde_json::json!({ "name": " Buy oat milk " }));
+    let todo: serde_json::Value = test::call_and_read_body_json(&app, request.to_request()).await;
+    assert_eq!(todo["name"], "Buy oat milk");
+}
+
+#[actix_web::test]
+async fn imports_can_be_as_large_as_the_limit_but_no_larger() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::register(&app, "alice").await;
+    browser.get(&app, "/import").await;
+
+    let mut file = r#"[{"name":"Padded"}]"#.to_string();
+    file += &" ".repeat(crate::transfer::MAX_IMPORT_SIZE - file.len());
+    let fields = [("format", "json"), ("list_id", "1")];
+    let page = browser.upload(&app, "/import", &fields, &file).await;
+    assert_eq!(page.status, StatusCode::SEE_OTHER, "{}", page.body);
+    assert_eq!(exported_todos(&app, &mut browser).await.len(), 1);
+
+    browser.get(&app, "/import").await;
+    let file = file + " ";
+    let page = browser.upload(&app, "/import", &fields, &file).await;
+    assert_eq!(page.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(page.body.contains("The file is larger than 10 MB"));
+}
+
+#[actix_web::test]
+async fn other_forms_are_capped_well_below_the_import_limit() {
+    let app = test::init_service(app(test_state().await, Key::generate())).await;
+    let mut browser = Browser::default();
+    browser.get(&app, "/login").await;
+
+    let password = "x".repeat(100 * 1024);
+    let page = browser
+        .post(
+            &app,
+            "/login",
+            &[("username", "alice"), ("password", &password)],
+        )
+        .await;
+    assert_eq!(page.status, StatusCode::PAYLOAD_TOO_LARGE);
+}
@@ -0,0 +1,871 @@
+use actix_multipart::Multipart;
+use actix_session::Session;
+use actix_web::{
+    get,
+    http::{header, header::ContentType, StatusCode},
+    post, web, HttpResponse,
+};
+use chrono::{NaiveDate, NaiveTime};
+use futures_util::{stream, StreamExt};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::csrf::CsrfToken;
+use crate::flash::{self, Flash};
+use crate::{
+    history, ical, list_url, multipart, page, recurrence, tags, Change, CurrentUser, Error, List,
+    Priority, State, Todo,
+};
+
+/// Uploads are read into memory whole, so they're capped.
+pub(crate) const MAX_IMPORT_SIZE: usize = 10 * 1024 * 1024;
+
+/// How many todos an export loads from the database at a time.
+const EXPORT_BATCH_SIZE: u32 = 500;
+
+const CSV_HEADERS: [&str; 10] = [
+    "id",
+    "parent_id",
+    "list",
+    "name",
+    "done",
+    "priority",
+    "due_date",
+    "due_time",
+    "tags",
+    "rrule",
+];
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Json,
+    Csv,
+    Todotxt,
+    Ics,
+}
+
+impl Format {
+    const ALL: [Format; 4] = [Format::Todotxt, Format::Csv, Format::Json, Format::Ics];
+
+    fn label(self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Csv => "CSV",
+            Format::Todotxt => "todo.txt",
+            Format::Ics => "iCalendar",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Todotxt => "todotxt",
+            Format::Ics => "ics",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Format::Json => "todos.json",
+            Format::Csv => "todos.csv",
+            Format::Todotxt => "todo.txt",
+            Format::Ics => "todos.ics",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Todotxt => "text/plain; charset=utf-8",
+            Format::Ics => ical::CONTENT_TYPE,
+        }
+    }
+}
+
+/// A todo as it's exported. `id` and `parent_id` only serve to rebuild
+/// subtasks on import, so parents have to come before their subtasks.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Record {
+    #[serde(default)]
+    pub(crate) id: Option<u32>,
+    #[serde(default)]
+    pub(crate) parent_id: Option<u32>,
+    /// The list's name; imports without one go to the list picked on the form.
+    #[serde(default)]
+    pub(crate) list: Option<String>,
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) done: bool,
+    #[serde(default)]
+    pub(crate) priority: Priority,
+    #[serde(default)]
+    pub(crate) due_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub(crate) due_time: Option<NaiveTime>,
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    #[serde(default)]
+    pub(crate) rrule: Option<String>,
+}
+
+impl Record {
+    /// Checks what the database would otherwise accept as is.
+    fn validate(mut self) -> Result<Record, String> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err("the name is empty".to_string());
+        }
+
+        self.rrule = match self.rrule.as_deref() {
+            Some(rrule) => recurrence::parse_rrule(rrule)
+                .map_err(|message| format!("invalid repeat rule: {}", message))?,
+            None => None,
+        };
+        self.tags = tags::parse_tags(&self.tags.join(" "));
+        self.list = self.list.filter(|list| !list.trim().is_empty());
+
+        Ok(self)
+    }
+}
+
+/// todo.txt priorities are letters, with `(A)` the most important.
+fn priority_letter(priority: Priority) -> Option<char> {
+    match priority {
+        Priority::None => None,
+        Priority::Low => Some('D'),
+        Priority::Medium => Some('C'),
+        Priority::High => Some('B'),
+        Priority::Urgent => Some('A'),
+    }
+}
+
+/// Letters past `D` are all treated as low priority.
+fn letter_priority(letter: &str) -> Option<Priority> {
+    match letter {
+        "A" => Some(Priority::Urgent),
+        "B" => Some(Priority::High),
+        "C" => Some(Priority::Medium),
+        "D" => Some(Priority::Low),
+        letter if letter.len() == 1 && letter.chars().all(|c| c.is_ascii_uppercase()) => {
+            Some(Priority::Low)
+        }
+        _ => None,
+    }
+}
+
+/// The `key:value` pairs `parse_todotxt` reads out of a line.
+const TODOTXT_KEYS: [&str; 5] = ["due", "at", "pri", "rrule", "tag"];
+
+/// A list name as a todo.txt `+project`, which can't contain spaces: they
+/// become `_`, and a literal `_` or `\` gets a `\` in front.
+fn encode_project(list: &str) -> String {
+    let mut project = String::new();
+    for (i, word) in list.split_whitespace().enumerate() {
+        if i > 0 {
+            project.push('_');
+        }
+        for c in word.chars() {
+            if c == '_' || c == '\\' {
+                project.push('\\');
+            }
+            project.push(c);
+        }
+    }
+    project
+}
+
+/// Reads a `+project` written by `encode_project`, or by another app that
+/// writes spaces as `_`.
+fn decode_project(project: &str) -> String {
+    let mut list = String::new();
+    let mut chars = project.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => list.extend(chars.next()),
+            '_' => list.push(' '),
+            c => list.push(c),
+        }
+    }
+    list
+}
+
+/// Whether `parse_todotxt` would read name word `word` as something else,
+/// `first` meaning it's the name's first word, which follows the `x `,
+/// priority and dates that only count at the start of the line.
+fn is_todotxt_markup(word: &str, first: bool) -> bool {
+    let leading = word == "x"
+        || word
+            .strip_prefix('(')
+            .and_then(|word| word.strip_suffix(')'))
+            .is_some_and(|letter| letter_priority(letter).is_some())
+        || NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok();
+
+    (first && leading)
+        || word.starts_with('\\')
+        || (word.len() > 1 && (word.starts_with('+') || word.starts_with('@')))
+        || word
+            .split_once(':')
+            .is_some_and(|(key, _)| TODOTXT_KEYS.contains(&key))
+}
+
+/// Writes `record` as a todo.txt line, e.g.
+/// `(A) Call mum +Home @phone due:2024-05-01 at:18:00`. Completed todos get
+/// an `x ` prefix and keep their priority as `pri:A`, as todo.txt suggests,
+/// and tags without an `@` or `+` are written as `tag:name`.
+/// Words of the name that would read as any of that, like a leading `x` or a
+/// `+word`, are escaped with a `\`.
+fn to_todotxt(record: &Record) -> String {
+    let mut words = Vec::new();
+
+    let letter = priority_letter(record.priority);
+    if record.done {
+        words.push("x".to_string());
+    } else if let Some(letter) = letter {
+        words.push(format!("({})", letter));
+    }
+
+    words.extend(
+        record
+            .name
+            .split_whitespace()
+            .enumerate()
+            .map(|(index, word)| {
+                if is_todotxt_markup(word, index == 0) {
+                    format!("\\{}", word)
+                } else {
+                    word.to_string()
+                }
+            }),
+    );
+
+    if let Some(list) = &record.list {
+        words.push(format!("+{}", encode_project(list)));
+    }
+    for tag in &record.tags {
+        // `+tags` only read back as tags after the list's `+project`.
+        let sigil = tag.starts_with('@') || (tag.starts_with('+') && record.list.is_some());
+        if tag.len() > 1 && sigil {
+            words.push(tag.clone());
+        } else {
+            words.push(format!("tag:{}", tag));
+        }
+    }
+
+    if let (true, Some(letter)) = (record.done, letter) {
+        words.push(format!("pri:{}", letter));
+    }
+    if let Some(date) = record.due_date {
+        words.push(format!("due:{}", date));
+    }
+    if let Some(time) = record.due_time {
+        words.push(format!("at:{}", time.format("%H:%M")));
+    }
+    if let Some(rrule) = &record.rrule {
+        words.push(format!("rrule:{}", rrule));
+    }
+
+    words.join(" ")
+}
+
+/// Reads a todo.txt line written by `to_todotxt`, or by any other todo.txt
+/// app. The first `+project` picks the list (with `_` read as a space, and
+/// `\_` as an underscore); any other projects, `@contexts` and `tag:name`s
+/// become tags. Completion and creation dates
+/// are skipped, and unknown `key:value` pairs stay in the name, as do words
+/// escaped with a leading `\`, which is dropped.
+fn parse_todotxt(line: &str) -> Result<Record, String> {
+    let mut record = Record::default();
+    let mut words = line.split_whitespace().peekable();
+
+    if words.peek() == Some(&"x") {
+        record.done = true;
+        words.next();
+    }
+    if let Some(priority) = words
+        .peek()
+        .and_then(|word| word.strip_prefix('(')?.strip_suffix(')'))
+        .and_then(letter_priority)
+    {
+        record.priority = priority;
+        words.next();
+    }
+    while words
+        .peek()
+        .is_some_and(|word| NaiveDate::parse_from_str(word, "%Y-%m-%d").is_ok())
+    {
+        words.next();
+    }
+
+    let mut name = Vec::new();
+    for word in words {
+        if let Some(word) = word.strip_prefix('\\') {
+            name.push(word);
+            continue;
+        }
+        if let Some(project) = word.strip_prefix('+').filter(|project| !project.is_empty()) {
+            match record.list {
+                None => record.list = Some(decode_project(project)),
+                Some(_) => record.tags.push(word.to_string()),
+            }
+            continue;
+        }
+        if word.len() > 1 && word.starts_with('@') {
+            record.tags.push(word.to_string());
+            continue;
+        }
+
+        match word.split_once(':') {
+            Some(("due", date)) => {
+                record.due_date = Some(
+                    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                        .map_err(|_| format!("invalid due date “{}”", date))?,
+                );
+            }
+            Some(("at", time)) => {
+                record.due_time = Some(
+                    NaiveTime::parse_from_str(time, "%H:%M")
+                        .map_err(|_| format!("invalid due time “{}”", time))?,
+                );
+            }
+            Some(("pri", letter)) => {
+                record.priority = letter_priority(letter)
+                    .ok_or_else(|| format!("invalid priority “{}”", letter))?;
+            }
+            Some(("rrule", rrule)) => record.rrule = Some(rrule.to_string()),
+            Some(("tag", tag)) if !tag.is_empty() => record.tags.push(tag.to_string()),
+            _ => name.push(word),
+        }
+    }
+    record.name = name.join(" ");
+
+    Ok(record)
+}
+
+fn parse_csv_record(
+    headers: &csv::StringRecord,
+    row: &csv::StringRecord,
+) -> Result<Record, String> {
+    let field = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .and_then(|index| row.get(index))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let id = |name: &str| {
+        field(name)
+            .map(|id| {
+                id.parse::<u32>()
+                    .map_err(|_| format!("invalid {} “{}”", name, id))
+            })
+            .transpose()
+    };
+
+    Ok(Record {
+        id: id("id")?,
+        parent_id: id("parent_id")?,
+        list: field("list").map(str::to_string),
+        name: field("name").unwrap_or_default().to_string(),
+        done: matches!(
+            field("done").map(str::to_lowercase).as_deref(),
+            Some("true" | "1" | "yes" | "x")
+        ),
+        priority: match field("priority") {
+            Some(name) => Priority::ALL
+                .into_iter()
+                .find(|priority| priority.name() == name.to_lowercase())
+                .ok_or_else(|| format!("invalid priority “{}”", name))?,
+            None => Priority::None,
+        },
+        due_date: field("due_date")
+            .map(|date| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| format!("invalid due date “{}”", date))
+            })
+            .transpose()?,
+        due_time: field("due_time")
+            .map(|time| {
+                NaiveTime::parse_from_str(time, "%H:%M")
+                    .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+                    .map_err(|_| format!("invalid due time “{}”", time))
+            })
+            .transpose()?,
+        tags: tags::parse_tags(field("tags").unwrap_or_default()),
+        rrule: field("rrule").map(str::to_string),
+    })
+}
+
+/// Reads an uploaded file into records, or explains what's wrong with it.
+fn parse_records(format: Format, data: &[u8]) -> Result<Vec<Record>, String> {
+    let checked = |location: String, record: Result<Record, String>| {
+        record
+            .and_then(Record::validate)
+            .map_err(|message| format!("{}: {}", location, message))
+    };
+
+    match format {
+        Format::Json => serde_json::from_slice::<Vec<Record>>(data)
+            .map_err(|err| format!("Invalid JSON: {}", err))?
+            .into_iter()
+            .enumerate()
+            .map(|(index, record)| checked(format!("Todo {}", index + 1), Ok(record)))
+            .collect(),
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(data);
+            let headers = reader
+                .headers()
+                .map_err(|err| format!("Invalid CSV: {}", err))?
+                .clone();
+            if !headers.iter().any(|header| header == "name") {
+                return Err("The CSV file needs a “name” column".to_string());
+            }
+
+            reader
+                .records()
+                .enumerate()
+                .map(|(index, row)| {
+                    let row = row.map_err(|err| format!("Invalid CSV: {}", err))?;
+                    checked(
+                        format!("Row {}", index + 2),
+                        parse_csv_record(&headers, &row),
+                    )
+                })
+                .collect()
+        }
+        Format::Todotxt => std::str::from_utf8(data)
+            .map_err(|_| "The file isn't UTF-8 text".to_string())?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| checked(format!("Line {}", index + 1), parse_todotxt(line)))
+            .collect(),
+        Format::Ics => std::str::from_utf8(data)
+            .map_err(|_| "The file isn't UTF-8 text".to_string())
+            .and_then(ical::parse_ics)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, record)| checked(format!("Todo {}", index + 1), Ok(record)))
+            .collect(),
+    }
+}
+
+/// Everything `user_id` hasn't deleted, or just what's in list `list_id`,
+/// parents before their subtasks.
+pub(crate) async fn load_records(
+    db: &sqlx::SqlitePool,
+    user_id: u32,
+    list_id: Option<u32>,
+) -> Result<Vec<Record>, sqlx::Error> {
+    let (records, _) = load_batch(db, user_id, list_id, (0, 0), u32::MAX).await?;
+    Ok(records)
+}
+
+/// Up to `limit` of the records `load_records` loads, starting after the
+/// todo at `after`, a list and todo id. Also returns where the next batch
+/// starts, if there might be one.
+async fn load_batch(
+    db: &sqlx::SqlitePool,
+    user_id: u32,
+    list_id: Option<u32>,
+    after: (u32, u32),
+    limit: u32,
+) -> Result<(Vec<Record>, Option<(u32, u32)>), sqlx::Error> {
+    let lists = sqlx::query_as::<_, List>(
+        "
+            SELECT id, name, sort FROM lists WHERE user_id=?
+        ",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    // Subtasks are always created after their parent, so ids are enough.
+    let mut todos = sqlx::query_as::<_, Todo>(
+        "
+            SELECT todos.* FROM todos JOIN lists ON lists.id = todos.list_id
+            WHERE lists.user_id = ?1 AND todos.deleted_at IS NULL AND (?2 IS NULL OR lists.id = ?2)
+                AND (todos.list_id, todos.id) > (?3, ?4)
+            ORDER BY todos.list_id, todos.id
+            LIMIT ?5
+        ",
+    )
+    .bind(user_id)
+    .bind(list_id)
+    .bind(after.0)
+    .bind(after.1)
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+
+    tags::load_tags(db, &mut todos).await?;
+
+    let next = todos
+        .last()
+        .filter(|_| todos.len() == limit as usize)
+        .map(|todo| (todo.list_id, todo.id));
+    let records = todos
+        .into_iter()
+        .map(|todo| Record {
+            id: Some(todo.id),
+            parent_id: todo.parent_id,
+            list: lists
+                .iter()
+                .find(|list| list.id == todo.list_id)
+                .map(|list| list.name.clone()),
+            name: todo.name,
+            done: todo.done,
+            priority: todo.priority,
+            due_date: todo.due_date,
+            due_time: todo.due_time,
+            tags: todo.tags,
+            rrule: todo.rrule,
+        })
+        .collect();
+
+    Ok((records, next))
+}
+
+/// Writes `records` as CSV rows, after the header row if `headers`.
+fn write_csv(records: &[Record], headers: bool) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if headers {
+        writer.write_record(CSV_HEADERS)?;
+    }
+
+    for record in records {
+        writer.write_record([
+            record.id.map(|id| id.to_string()).unwrap_or_default(),
+            record
+                .parent_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            record.list.clone().unwrap_or_default(),
+            record.name.clone(),
+            record.done.to_string(),
+            record.priority.name().to_string(),
+            record
+                .due_date
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+            record
+                .due_time
+                .map(|time| time.format("%H:%M").to_string())
+                .unwrap_or_default(),
+            record.tags.join(" "),
+            record.rrule.clone().unwrap_or_default(),
+        ])?;
+    }
+
+    writer
+        .into_inner()
+        .map_err(|err| csv::Error::from(err.into_error()))
+}
+
+/// A batch of `records` in the middle of an export, `first` meaning nothing
+/// but the start came before it.
+fn write_batch(
+    format: Format,
+    records: &[Record],
+    first: bool,
+    stamp: &str,
+) -> Result<Vec<u8>, Error> {
+    Ok(match format {
+        Format::Json => {
+            let mut json = Vec::new();
+            for (index, record) in records.iter().enumerate() {
+                json.extend_from_slice(if first && index == 0 { b"\n" } else { b",\n" });
+                serde_json::to_writer(&mut json, record).map_err(|_| Error::Export)?;
+            }
+            json
+        }
+        Format::Csv => write_csv(records, false).map_err(|_| Error::Export)?,
+        Format::Todotxt => records
+            .iter()
+            .map(|record| to_todotxt(record) + "\n")
+            .collect::<String>()
+            .into_bytes(),
+        Format::Ics => {
+            let mut ics = String::new();
+            for record in records {
+                ical::push_todo(&mut ics, record, stamp);
+            }
+            ics.into_bytes()
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: Format,
+}
+
+/// Streams the export a batch at a time, so large ones aren't built up in
+/// memory.
+#[get("/export")]
+async fn export(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    web::Query(query): web::Query<ExportQuery>,
+) -> Result<HttpResponse, Error> {
+    let format = query.format;
+    let (start, end) = match format {
+        Format::Json => ("[".to_string(), "\n]\n".to_string()),
+        Format::Csv => (
+            String::from_utf8(write_csv(&[], true).map_err(|_| Error::Export)?)
+                .map_err(|_| Error::Export)?,
+            String::new(),
+        ),
+        Format::Todotxt => (String::new(), String::new()),
+        Format::Ics => (ical::calendar_start(None), ical::calendar_end()),
+    };
+    let stamp = ical::stamp();
+
+    // Where the next batch starts, and whether no todos came before it.
+    let batches = stream::unfold(Some(((0, 0), true)), move |next| {
+        let (state, stamp) = (state.clone(), stamp.clone());
+        async move {
+            let (after, first) = next?;
+            let batch = load_batch(&state.db, user.id, None, after, EXPORT_BATCH_SIZE).await;
+            Some(match batch {
+                Ok((records, next)) => (
+                    write_batch(format, &records, first, &stamp).map(web::Bytes::from),
+                    next.map(|next| (next, first && records.is_empty())),
+                ),
+                Err(err) => (Err(Error::from(err)), None),
+            })
+        }
+    });
+    let body = stream::once(async { Ok(web::Bytes::from(start)) })
+        .chain(batches)
+        .chain(stream::once(async { Ok(web::Bytes::from(end)) }));
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, format.content_type()))
+        .insert_header(header::ContentDisposition::attachment(format.file_name()))
+        .streaming(body))
+}
+
+/// Creates a todo for each of `records` on behalf of `user_id`. Records name
+/// their list; missing lists are created, and records without one go to
+/// `default_list_id`. Returns the new todos' ids and lists.
+async fn import_records(
+    conn: &mut SqliteConnection,
+    user_id: u32,
+    default_list_id: u32,
+    records: Vec<Record>,
+) -> Result<Vec<(u32, u32)>, sqlx::Error> {
+    let mut lists: HashMap<String, u32> = HashMap::new();
+    for (id, name) in sqlx::query_as::<_, (u32, String)>(
+        "
+            SELECT id, name FROM lists WHERE user_id=? ORDER BY id DESC
+        ",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        // Going newest first leaves the oldest of same-named lists.
+        lists.insert(name, id);
+    }
+
+    // Ids from the file, mapped to the new todo's id and list.
+    let mut imported: HashMap<u32, (u32, u32)> = HashMap::new();
+    let mut created = Vec::with_capacity(records.len());
+
+    for record in &records {
+        let list_id = match &record.list {
+            Some(name) => match lists.get(name) {
+                Some(list_id) => *list_id,
+                None => {
+                    let list_id = sqlx::query_scalar::<_, u32>(
+                        "
+                            INSERT INTO lists (name, user_id) VALUES (?, ?) RETURNING id
+                        ",
+                    )
+                    .bind(name)
+                    .bind(user_id)
+                    .fetch_one(&mut *conn)
+                    .await?;
+                    lists.insert(name.clone(), list_id);
+                    list_id
+                }
+            },
+            None => default_list_id,
+        };
+
+        // Subtasks whose parent is missing or in another list become top-level todos.
+        let parent_id = record
+            .parent_id
+            .and_then(|parent_id| imported.get(&parent_id))
+            .filter(|(_, parent_list_id)| *parent_list_id == list_id)
+            .map(|(parent_id, _)| *parent_id);
+
+        let id = sqlx::query_scalar::<_, u32>(
+            "
+                INSERT INTO todos (name, done, list_id, due_date, due_time, parent_id, priority, rrule, position)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, (
+                    SELECT COALESCE(MAX(position) + 1, 0) FROM todos
+                    WHERE list_id = ?3 AND parent_id IS ?6
+                ))
+                RETURNING id
+            ",
+        )
+        .bind(&record.name)
+        .bind(record.done)
+        .bind(list_id)
+        .bind(record.due_date)
+        .bind(record.due_time.filter(|_| record.due_date.is_some()))
+        .bind(parent_id)
+        .bind(record.priority)
+        .bind(&record.rrule)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        tags::set_tags(conn, user_id, id, &record.tags).await?;
+        history::record(conn, id, Some(user_id), history::Action::Created).await?;
+
+        if let Some(old_id) = record.id {
+            imported.insert(old_id, (id, list_id));
+        }
+        created.push((id, list_id));
+    }
+
+    Ok(created)
+}
+
+fn import_page(lists: &[List], error: Option<&str>, csrf: &CsrfToken) -> Markup {
+    page(
+        "Import and export",
+        html! {
+            nav class="lists" {
+                a href="/" { "← Back to lists" }
+            }
+            h1 { "Export" }
+            p class="search-summary" { "Download all of your todos, except those in the trash." }
+            nav class="views" {
+                @for format in Format::ALL {
+                    a href=(format!("/export?format={}", format.name())) { (format.label()) }
+                }
+            }
+            h1 { "Import" }
+            p class="search-summary" {
+                "Todos are added to the list they name, which is created if needed. "
+                "Todos that don't name one go to the list below."
+            }
+            @if let Some(error) = error {
+                p class="error" { (error) }
+            }
+            form class="credentials" action="/import" method="POST" enctype="multipart/form-data" {
+                (csrf)
+                input type="file" name="file" required;
+                select name="format" aria-label="Format" {
+                    @for format in Format::ALL {
+                        option value=(format.name()) { (format.label()) }
+                    }
+                }
+                select name="list_id" aria-label="List" {
+                    @for list in lists {
+                        option value=(list.id) { (list.name) }
+                    }
+                }
+                button { "Import" }
+            }
+        },
+    )
+}
+
+async fn load_lists(state: &State, user: &CurrentUser) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as::<_, List>(
+        "
+            SELECT id, name, sort FROM lists WHERE user_id=? ORDER BY id
+        ",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db)
+    .await
+}
+
+#[get("/import")]
+async fn show_import(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    csrf: CsrfToken,
+) -> Result<Markup, Error> {
+    Ok(import_page(&load_lists(&state, &user).await?, None, &csrf))
+}
+
+#[post("/import")]
+async fn import(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    session: Session,
+    csrf: CsrfToken,
+    multipart: Multipart,
+) -> Result<HttpResponse, Error> {
+    let lists = load_lists(&state, &user).await?;
+
+    let error = |message: &str| {
+        HttpResponse::build(StatusCode::UNPROCESSABLE_ENTITY)
+            .content_type(ContentType::html())
+            .body(import_page(&lists, Some(message), &csrf).into_string())
+    };
+
+    let limit = MAX_IMPORT_SIZE + multipart::OVERHEAD;
+    let Some(mut fields) = multipart::read_fields(multipart, limit).await else {
+        return Ok(error("The upload couldn't be read"));
+    };
+
+    let format = fields
+        .get("format")
+        .and_then(|format| std::str::from_utf8(format).ok())
+        .and_then(|format| Format::ALL.into_iter().find(|f| f.name() == format));
+    let list_id = fields
+        .get("list_id")
+        .and_then(|list_id| std::str::from_utf8(list_id).ok())
+        .and_then(|list_id| list_id.parse::<u32>().ok())
+        .filter(|list_id| lists.iter().any(|list| list.id == *list_id));
+    let (Some(format), Some(list_id), Some(data)) = (format, list_id, fields.remove("file")) else {
+        return Ok(error("Pick a file, a format and a list"));
+    };
+    if data.len() > MAX_IMPORT_SIZE {
+        return Ok(error(&format!(
+            "The file is larger than {} MB",
+            MAX_IMPORT_SIZE / 1024 / 1024
+        )));
+    }
+
+    let records = match parse_records(format, &data) {
+        Ok(records) => records,
+        Err(message) => return Ok(error(&message)),
+    };
+
+    let mut tx = state.db.begin().await?;
+    let created = import_records(&mut tx, user.id, list_id, records).await?;
+    tx.commit().await?;
+
+    for &(id, list_id) in &created {
+        state.live.publish(user.id, Change::Created, id, list_id);
+    }
+
+    flash::set(
+        &session,
+        Flash::Imported {
+            count: created.len(),
+        },
+    );
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, list_url(Some(list_id))))
+        .finish())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(export).service(show_import).service(import);
+}
@@ -0,0 +1,322 @@
+use actix_web::{get, post, rt, web, Responder};
+use chrono::NaiveDateTime;
+use maud::{html, Markup};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::csrf::CsrfToken;
+use crate::{history, list_url, page, Change, CurrentUser, Error, State};
+
+/// How often the background task looks for old trash.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Moves todo `todo_id` and its subtasks to the trash on behalf of
+/// `actor_id`. They're all stamped with the same `deleted_at`, which is how
+/// `restore` finds them again.
+pub(crate) async fn trash_todo(
+    conn: &mut SqliteConnection,
+    todo_id: u32,
+    actor_id: u32,
+) -> Result<bool, sqlx::Error> {
+    let trashed = sqlx::query_scalar::<_, u32>(
+        "
+            WITH RECURSIVE subtree(id) AS (
+                SELECT ?
+                UNION ALL
+                SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+            )
+            UPDATE todos SET deleted_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+            WHERE id IN subtree AND deleted_at IS NULL
+            RETURNING id
+        ",
+    )
+    .bind(todo_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for id in &trashed {
+        history::record(conn, *id, Some(actor_id), history::Action::Deleted).await?;
+    }
+
+    Ok(!trashed.is_empty())
+}
+
+/// Permanently deletes todos that have been in the trash for longer than
+/// `max_age_days`. Returns how many were deleted.
+pub(crate) async fn purge_trash(db: &SqlitePool, max_age_days: u32) -> Result<u64, sqlx::Error> {
+    let cutoff = format!("-{} days", max_age_days);
+
+    let mut tx = db.begin().await?;
+
+    history::record_purged(
+        &mut tx,
+        None,
+        "deleted_at < datetime('now', ?)",
+        cutoff.clone(),
+    )
+    .await?;
+
+    let result = sqlx::query(
+        "
+            DELETE FROM todos WHERE deleted_at < datetime('now', ?)
+        ",
+    )
+    .bind(&cutoff)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Runs `purge_trash` every `PURGE_INTERVAL` for as long as the server runs.
+pub(crate) fn spawn_purge_task(db: SqlitePool, max_age_days: u32) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match purge_trash(&db, max_age_days).await {
+                Ok(0) => {}
+                Ok(purged) => log::info!("Purged {} todos from the trash", purged),
+                Err(err) => log::error!("Failed to purge trash: {}", err),
+            }
+        }
+    });
+}
+
+#[derive(sqlx::FromRow)]
+struct TrashedTodo {
+    id: u32,
+    name: String,
+    list_id: u32,
+    list_name: String,
+    deleted_at: NaiveDateTime,
+    subtasks: u32,
+}
+
+/// Lists everything the user deleted, except subtasks that went along with
+/// their parent.
+#[get("/trash")]
+async fn show_trash(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    csrf: CsrfToken,
+) -> Result<Markup, Error> {
+    let todos = sqlx::query_as::<_, TrashedTodo>(
+        "
+            SELECT todos.id, todos.name, todos.list_id, lists.name AS list_name, todos.deleted_at,
+                (SELECT COUNT(*) FROM todos AS subtasks
+                    WHERE subtasks.parent_id = todos.id AND subtasks.deleted_at = todos.deleted_at
+                ) AS subtasks
+            FROM todos JOIN lists ON lists.id = todos.list_id
+            WHERE lists.user_id = ? AND todos.deleted_at IS NOT NULL AND NOT EXISTS (
+                SELECT 1 FROM todos AS parents
+                WHERE parents.id = todos.parent_id AND parents.deleted_at = todos.deleted_at
+            )
+            ORDER BY todos.deleted_at DESC, todos.id
+        ",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(page(
+        "Trash",
+        html! {
+            nav class="lists" {
+                a href="/" { "← Back to lists" }
+            }
+            div class="list-header" {
+                h1 { "Trash" }
+                @if !todos.is_empty() {
+                    form action="/trash/empty" method="POST" {
+                        (csrf)
+                        button { "Empty trash" }
+                    }
+                }
+            }
+            @if todos.is_empty() {
+                p class="search-summary" { "The trash is empty." }
+            }
+            ul class="trash" {
+                @for todo in &todos {
+                    li {
+                        span class="text" { (todo.name) }
+                        @if todo.subtasks > 0 {
+                            span class="progress" { "+" (todo.subtasks) " subtask" @if todo.subtasks != 1 { "s" } }
+                        }
+                        a class="list-link" href=(format!("/lists/{}", todo.list_id)) { (todo.list_name) }
+                        time class="due" datetime=(todo.deleted_at.format("%Y-%m-%dT%H:%M:%SZ")) {
+                            (todo.deleted_at.format("%-d %b %Y %H:%M"))
+                        }
+                        form action=(format!("/todos/{}/restore", todo.id)) method="POST" {
+                            (csrf)
+                            button { "Restore" }
+                        }
+                        form action=(format!("/todos/{}/purge", todo.id)) method="POST" {
+                            (csrf)
+                            button class="delete" aria-label="Delete forever" { "❌" }
+                        }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Takes todo `id` and the subtasks deleted along with it back out of the
+/// trash. If its parent is still in the trash, it comes back at the top level.
+#[post("/todos/{id}/restore")]
+async fn restore(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_deleted_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    // Kept as text so it compares equal to the subtasks' stamps.
+    let (list_id, deleted_at) = sqlx::query_as::<_, (u32, String)>(
+        "
+            SELECT list_id, deleted_at FROM todos WHERE id=?
+        ",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "
+            UPDATE todos SET parent_id = NULL
+            WHERE id = ? AND parent_id IN (SELECT id FROM todos WHERE deleted_at IS NOT NULL)
+        ",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    let restored = sqlx::query_scalar::<_, u32>(
+        "
+            WITH RECURSIVE subtree(id) AS (
+                SELECT ?1
+                UNION ALL
+                SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+                WHERE todos.deleted_at = ?2
+            )
+            UPDATE todos SET deleted_at = NULL WHERE id IN subtree
+            RETURNING id
+        ",
+    )
+    .bind(id)
+    .bind(deleted_at)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for &id in &restored {
+        history::record(&mut tx, id, Some(user.id), history::Action::Restored).await?;
+    }
+
+    tx.commit().await?;
+
+    // To the list, a restored todo is as good as new.
+    for id in restored {
+        state.live.publish(user.id, Change::Created, id, list_id);
+    }
+
+    Ok(web::Redirect::to(list_url(Some(list_id))).see_other())
+}
+
+/// Permanently deletes todo `id` (and, through the foreign key, its subtasks).
+#[post("/todos/{id}/purge")]
+async fn purge(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+    id: web::Path<u32>,
+) -> Result<impl Responder, Error> {
+    let id = id.into_inner();
+
+    if !user.owns_deleted_todo(&state.db, id).await? {
+        return Err(Error::NotFound);
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    history::record_purged(
+        &mut tx,
+        Some(user.id),
+        "
+            id IN (
+                WITH RECURSIVE subtree(id) AS (
+                    SELECT ?
+                    UNION ALL
+                    SELECT todos.id FROM todos JOIN subtree ON todos.parent_id = subtree.id
+                )
+                SELECT id FROM subtree
+            )
+        ",
+        id,
+    )
+    .await?;
+
+    let deleted = sqlx::query(
+        "
+            DELETE FROM todos WHERE id=? AND deleted_at IS NOT NULL
+        ",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    if deleted.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    tx.commit().await?;
+
+    Ok(web::Redirect::to("/trash").see_other())
+}
+
+#[post("/trash/empty")]
+async fn empty_trash(
+    state: web::Data<Arc<State>>,
+    user: CurrentUser,
+) -> Result<impl Responder, Error> {
+    let mut tx = state.db.begin().await?;
+
+    history::record_purged(
+        &mut tx,
+        Some(user.id),
+        "deleted_at IS NOT NULL AND list_id IN (SELECT id FROM lists WHERE user_id = ?)",
+        user.id,
+    )
+    .await?;
+
+    sqlx::query(
+        "
+            DELETE FROM todos
+            WHERE deleted_at IS NOT NULL
+            AND list_id IN (SELECT id FROM lists WHERE user_id = ?)
+        ",
+    )
+    .bind(user.id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(web::Redirect::to("/trash").see_other())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(show_trash)
+        .service(restore)
+        .service(purge)
+        .service(empty_trash);
+}